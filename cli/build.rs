@@ -0,0 +1,5 @@
+fn main() {
+    // export the host functions of the emulator from the binaries,
+    // so that the contract plugins loaded at runtime resolve their imports against them
+    println!("cargo:rustc-link-arg-bins=-rdynamic");
+}
@@ -0,0 +1,94 @@
+//! hmemu-repl runs commands against contracts in an emulator process.
+//!
+//! ```text
+//! $ hmemu-repl target/debug/libtoken.so
+//! > sender 0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c
+//! > call transfer 0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed 100u64
+//! status 0
+//! return 0x000000000000038e
+//! > commit
+//! ```
+use hmcdk::error;
+use hmemu::{run_forked_process, run_process, ForkedProcess, Result};
+use hmemu_cli::repl::{Repl, Step};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    let mut repl = Repl::new();
+    for path in std::env::args().skip(1) {
+        if let Err(e) = repl.load(Path::new(&path)) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let mut editor = DefaultEditor::new().expect("failed to initialize the line editor");
+    let history = history_path();
+    if let Some(ref path) = history {
+        // the history doesn't exist at the first run
+        let _ = editor.load_history(path);
+    }
+
+    // every restore of a snapshot leaves the current process, and continues in the snapshot
+    let mut restore = None;
+    loop {
+        let res = match restore.take() {
+            None => run_process(AssertUnwindSafe(|| run(&mut repl, &mut editor))),
+            Some((n, forked)) => run_forked_process(
+                forked,
+                AssertUnwindSafe(|| {
+                    repl.restored(n)?;
+                    run(&mut repl, &mut editor)
+                }),
+            ),
+        };
+        match res {
+            Ok(Some(next)) => restore = Some(next),
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(ref path) = history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("failed to save the history: {}", e);
+        }
+    }
+}
+
+// run executes commands until the user quits, or restores a snapshot.
+fn run(repl: &mut Repl, editor: &mut DefaultEditor) -> Result<Option<(usize, ForkedProcess)>> {
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(error::from_str(e.to_string())),
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        match repl.execute(&line) {
+            Ok(Step::Print(out)) => {
+                if !out.is_empty() {
+                    println!("{}", out);
+                }
+            }
+            Ok(Step::Restore(n, forked)) => {
+                println!("restored snapshot {}", n);
+                return Ok(Some((n, forked)));
+            }
+            Ok(Step::Quit) => return Ok(None),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(".hmemu_history"))
+}
@@ -0,0 +1,38 @@
+//! hmemu-scenario runs scenario files and reports the result of every step.
+//!
+//! ```text
+//! $ hmemu-scenario scenarios/token.yaml
+//! scenario token
+//!   ok   1. deploy 0xD8eBA1f372b9e0D378259F150d52c2E6C2e4109A from ../target/debug/libtoken.so
+//!   ok   2. call mint(u64:100)
+//!   ok   3. expect-return u64:100
+//! 3 of 3 steps passed
+//! ```
+//!
+//! It exits with 1 if a step of any scenario fails.
+use hmemu::run_scenario;
+use std::process::exit;
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: hmemu-scenario <scenario.yaml|scenario.json>...");
+        exit(2);
+    }
+    let mut passed = true;
+    for path in paths.iter() {
+        match run_scenario(path) {
+            Ok(report) => {
+                print!("{}", report);
+                passed &= report.passed();
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                passed = false;
+            }
+        }
+    }
+    if !passed {
+        exit(1);
+    }
+}
@@ -0,0 +1,2 @@
+pub mod repl;
+pub mod value;
@@ -0,0 +1,365 @@
+//! Commands of `hmemu-repl`.
+use crate::value::{parse_key, parse_value, tokenize, Word};
+use hmcdk::error;
+use hmcdk::prelude::*;
+use hmemu::abi::format_value;
+use hmemu::address::{address_from_label, from_hex, to_checksum_hex};
+use hmemu::plugin::Plugin;
+use hmemu::{
+    clear, commit_state, discard_state, fork_process, get_events, get_return_value, get_state,
+    init_push_arg, init_sender, pop_contract_state, push_contract_state,
+    register_contract_function, ContractFn, Event, ForkedProcess, Result,
+};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+pub const HELP: &str = "\
+load <path>                 load a contract plugin built as a cdylib
+contract [0x..]             show or set the address of the contract to call
+sender [0x..]               show or set the sender of calls
+call <entry> [args..]       call an entry of the contract without committing its writes
+commit                      commit the writes of the calls
+state get <key>             show the committed value of a key of the contract
+state list                  show the committed state of the contract
+events                      show the events emitted by the calls
+snapshot                    save the committed state to restore later
+restore <n>                 restore the snapshot n, dropping uncommitted writes
+help                        show this help
+quit                        exit
+
+Arguments are integers with a type suffix (100i64, 7u8), true or false,
+0x-prefixed addresses or hex bytes, or double-quoted strings.";
+
+/// Step is what the loop running the REPL does after a command.
+pub enum Step {
+    /// Print prints the output of the command and reads the next one.
+    Print(String),
+    /// Restore leaves the current process and continues in the snapshot.
+    Restore(usize, ForkedProcess),
+    Quit,
+}
+
+/// Repl is a session of `hmemu-repl`. Its commands run in the process attached to the current thread.
+pub struct Repl {
+    plugins: Vec<Plugin>,
+    functions: HashMap<String, ContractFn>,
+    contract: Address,
+    sender: Address,
+    snapshots: Vec<Option<ForkedProcess>>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl {
+            plugins: Vec::new(),
+            functions: HashMap::new(),
+            contract: address_from_label("contract"),
+            sender: address_from_label("sender"),
+            snapshots: Vec::new(),
+        }
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// load loads a contract plugin, whose entries are looked up before those of the plugins loaded earlier.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        self.plugins.push(Plugin::load(path)?);
+        Ok(())
+    }
+
+    /// define defines an entry of a contract linked into the binary, which is looked up before the plugins.
+    pub fn define(&mut self, entry: &str, f: ContractFn) {
+        self.functions.insert(entry.to_string(), f);
+    }
+
+    /// restored saves the snapshot again after the current process was restored from it,
+    /// so that it can be restored more than once.
+    pub fn restored(&mut self, n: usize) -> Result<()> {
+        self.snapshots[n] = Some(fork_process()?);
+        Ok(())
+    }
+
+    /// execute runs a command line.
+    pub fn execute(&mut self, line: &str) -> Result<Step> {
+        let words = tokenize(line)?;
+        let (cmd, args) = match words.split_first() {
+            Some((cmd, args)) => (cmd.text.as_str(), args),
+            None => return Ok(Step::Print(String::new())),
+        };
+        let out = match (cmd, args) {
+            ("load", [path]) => {
+                self.load(Path::new(&path.text))?;
+                format!("loaded {}", path.text)
+            }
+            ("contract", []) => to_checksum_hex(&self.contract),
+            ("contract", [addr]) => {
+                self.contract = from_hex(&addr.text)?;
+                to_checksum_hex(&self.contract)
+            }
+            ("sender", []) => to_checksum_hex(&self.sender),
+            ("sender", [addr]) => {
+                self.sender = from_hex(&addr.text)?;
+                to_checksum_hex(&self.sender)
+            }
+            ("call", [entry, args @ ..]) => self.call(&entry.text, args)?,
+            ("commit", []) => {
+                commit_state()?;
+                "committed".to_string()
+            }
+            ("state", [sub, key]) if sub.text == "get" => {
+                match get_state()?.get(&self.contract, &parse_key(key)) {
+                    Some(v) => format_value("_", v),
+                    None => "(none)".to_string(),
+                }
+            }
+            ("state", [sub]) if sub.text == "list" => {
+                let state = get_state()?;
+                let lines: Vec<String> = state
+                    .contract(&self.contract)
+                    .map(|(k, v)| format!("{} = {}", format_value("_", k), format_value("_", v)))
+                    .collect();
+                lines.join("\n")
+            }
+            ("events", []) => format_events(&get_events()?),
+            ("snapshot", []) => {
+                self.snapshots.push(Some(fork_process()?));
+                format!("snapshot {}", self.snapshots.len() - 1)
+            }
+            ("restore", [n]) => {
+                let n: usize = n
+                    .text
+                    .parse()
+                    .map_err(|_| error::from_str(format!("invalid snapshot `{}`", n.text)))?;
+                match self.snapshots.get_mut(n).and_then(|s| s.take()) {
+                    Some(forked) => return Ok(Step::Restore(n, forked)),
+                    None => return Err(error::from_str(format!("snapshot {} not found", n))),
+                }
+            }
+            ("help", []) => HELP.to_string(),
+            ("quit", []) | ("exit", []) => return Ok(Step::Quit),
+            _ => {
+                return Err(error::from_str(format!(
+                    "invalid command `{}`, see `help`",
+                    line.trim()
+                )))
+            }
+        };
+        Ok(Step::Print(out))
+    }
+
+    fn function(&self, entry: &str) -> Result<ContractFn> {
+        if let Some(f) = self.functions.get(entry) {
+            return Ok(*f);
+        }
+        self.plugins
+            .iter()
+            .rev()
+            .find_map(|p| p.function(entry))
+            .ok_or_else(|| error::from_str(format!("function not found: entry={}", entry)))
+    }
+
+    // call runs the entry like `invoke`, but leaves its writes uncommitted until `commit`.
+    // Each call runs in a frame of its own, so that the writes of the calls before it stay pending,
+    // and the writes of a call which fails or panics are discarded.
+    fn call(&mut self, entry: &str, words: &[Word]) -> Result<String> {
+        let f = self.function(entry)?;
+        let args = words.iter().map(parse_value).collect::<Result<Vec<_>>>()?;
+        // register the entry so that other contracts can call it
+        register_contract_function(self.contract, entry.to_string(), f);
+        let n_events = get_events()?.len();
+        push_contract_state(&self.contract)?;
+        let status = init_sender(&self.sender)
+            .and_then(|_| args.iter().try_for_each(|arg| init_push_arg(arg)))
+            .map(|_| catch_unwind(AssertUnwindSafe(f)));
+        let value: Result<Vec<u8>> = match status {
+            Ok(Ok(status)) if status >= 0 => get_return_value(),
+            _ => discard_state().map(|_| Vec::new()),
+        };
+        pop_contract_state()?;
+        clear()?;
+        let value = value?;
+        let status = match status? {
+            Ok(status) => status,
+            Err(_) => return Err(error::from_str(format!("{} panicked", entry))),
+        };
+        let mut out = format!("status {}", status);
+        if !value.is_empty() {
+            out.push_str(&format!("\nreturn {}", format_value("_", &value)));
+        }
+        let events = get_events()?.split_off(n_events);
+        if !events.is_empty() {
+            out.push('\n');
+            out.push_str(&format_events(&events));
+        }
+        Ok(out)
+    }
+}
+
+fn format_events(events: &[Event]) -> String {
+    let lines: Vec<String> = events
+        .iter()
+        .map(|e| format!("event {} {}", e.name, format_value("_", &e.value)))
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmcdk::api;
+    use hmemu::{run_forked_process, run_process};
+
+    fn add() -> i32 {
+        let x: i64 = api::get_arg(0).unwrap();
+        let y: i64 = api::get_arg(1).unwrap();
+        api::write_state("sum".as_bytes(), &(x + y).to_bytes());
+        api::emit_event("added", "ok".as_bytes()).unwrap();
+        api::return_value(&(x + y).to_bytes())
+    }
+
+    fn put() -> i32 {
+        let x: i64 = api::get_arg(0).unwrap();
+        api::write_state("put".as_bytes(), &x.to_bytes());
+        api::return_value(&[])
+    }
+
+    fn fail() -> i32 {
+        api::write_state("sum".as_bytes(), &0i64.to_bytes());
+        api::write_state("put".as_bytes(), &0i64.to_bytes());
+        -1
+    }
+
+    fn boom() -> i32 {
+        api::write_state("sum".as_bytes(), &0i64.to_bytes());
+        panic!("boom");
+    }
+
+    fn print(repl: &mut Repl, line: &str) -> String {
+        match repl.execute(line).unwrap() {
+            Step::Print(out) => out,
+            _ => panic!("unexpected step: {}", line),
+        }
+    }
+
+    fn error(repl: &mut Repl, line: &str) -> String {
+        match repl.execute(line) {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("expect error: {}", line),
+        }
+    }
+
+    #[test]
+    fn repl_test() {
+        let mut repl = Repl::new();
+        repl.define("add", add);
+        repl.define("put", put);
+        repl.define("fail", fail);
+        repl.define("boom", boom);
+        run_process(AssertUnwindSafe(|| {
+            assert_eq!(
+                "0x1221a0726d56aEdeA9dBe2522DdAE3Dd8ED0f36c",
+                print(
+                    &mut repl,
+                    "sender 0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c"
+                )
+            );
+            assert_eq!(
+                "status 0\nreturn 0x0000000000000003\nevent added \"ok\"",
+                print(&mut repl, "call add 1i64 2i64")
+            );
+            // the writes of a call are committed only by commit
+            assert_eq!("(none)", print(&mut repl, "state get sum"));
+            assert_eq!("committed", print(&mut repl, "commit"));
+            assert_eq!("0x0000000000000003", print(&mut repl, "state get \"sum\""));
+            assert_eq!(
+                "\"sum\" = 0x0000000000000003",
+                print(&mut repl, "state list")
+            );
+            assert_eq!("event added \"ok\"", print(&mut repl, "events"));
+
+            // the writes of the calls before a commit are all committed by it
+            print(&mut repl, "call add 2i64 2i64");
+            assert_eq!("status 0", print(&mut repl, "call put 1i64"));
+            assert_eq!("committed", print(&mut repl, "commit"));
+            assert_eq!("0x0000000000000004", print(&mut repl, "state get \"sum\""));
+            assert_eq!("0x0000000000000001", print(&mut repl, "state get \"put\""));
+
+            // the writes of a failing or panicking call are not
+            assert_eq!("status -1", print(&mut repl, "call fail"));
+            assert_eq!("add panicked", error(&mut repl, "call add 1i64"));
+            assert_eq!("boom panicked", error(&mut repl, "call boom"));
+            assert_eq!("committed", print(&mut repl, "commit"));
+            assert_eq!("0x0000000000000004", print(&mut repl, "state get \"sum\""));
+            assert_eq!("0x0000000000000001", print(&mut repl, "state get \"put\""));
+
+            assert_eq!(
+                "function not found: entry=sub",
+                error(&mut repl, "call sub 1i64 2i64")
+            );
+            assert!(error(&mut repl, "call add 1").starts_with("invalid value `1`"));
+            assert_eq!(
+                "invalid command `state put sum 1i64`, see `help`",
+                error(&mut repl, "state put sum 1i64")
+            );
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn snapshot_test() {
+        let mut repl = Repl::new();
+        repl.define("add", add);
+        let forked = run_process(AssertUnwindSafe(|| {
+            print(&mut repl, "call add 1i64 2i64");
+            print(&mut repl, "commit");
+            assert_eq!("snapshot 0", print(&mut repl, "snapshot"));
+            print(&mut repl, "call add 2i64 2i64");
+            print(&mut repl, "commit");
+            match repl.execute("restore 0")? {
+                Step::Restore(0, forked) => Ok(forked),
+                _ => panic!("unexpected step"),
+            }
+        }))
+        .unwrap();
+        // a snapshot can be restored again
+        let forked = run_forked_process(
+            forked,
+            AssertUnwindSafe(|| {
+                repl.restored(0)?;
+                assert_eq!("0x0000000000000003", print(&mut repl, "state get sum"));
+                print(&mut repl, "call add 2i64 2i64");
+                print(&mut repl, "commit");
+                match repl.execute("restore 0")? {
+                    Step::Restore(0, forked) => Ok(forked),
+                    _ => panic!("unexpected step"),
+                }
+            }),
+        )
+        .unwrap();
+        run_forked_process(
+            forked,
+            AssertUnwindSafe(|| {
+                repl.restored(0)?;
+                assert_eq!("0x0000000000000003", print(&mut repl, "state get sum"));
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn load_wasm_test() {
+        let path = std::env::temp_dir().join("hmemu_repl_test.wasm");
+        std::fs::write(&path, b"\0asm\x01\0\0\0").unwrap();
+        let err = Repl::new()
+            .load(&path)
+            .expect_err("expect wasm to be rejected");
+        assert!(err.to_string().contains("no wasm engine"));
+    }
+}
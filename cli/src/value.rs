@@ -0,0 +1,167 @@
+//! Values typed on the command line, e.g. `100i64`, `0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c` or `"name"`.
+use hmcdk::error;
+use hmcdk::prelude::*;
+use hmemu::Result;
+
+/// Word is a word of a command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub text: String,
+    /// quoted is true if the word was double-quoted, so that it is a string whatever it looks like.
+    pub quoted: bool,
+}
+
+/// tokenize splits a command line into words separated by whitespace.
+/// A double-quoted word may contain whitespace, and `\"` and `\\` escape a quote and a backslash in it.
+pub fn tokenize(line: &str) -> Result<Vec<Word>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut text = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => text.push(c),
+                        None => return Err(error::from_str("unterminated string".to_string())),
+                    },
+                    Some(c) => text.push(c),
+                    None => return Err(error::from_str("unterminated string".to_string())),
+                }
+            }
+            words.push(Word { text, quoted: true });
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+            words.push(Word {
+                text,
+                quoted: false,
+            });
+        }
+    }
+    Ok(words)
+}
+
+macro_rules! parse_int {
+    ($num:expr, $suffix:expr, $($t:ty),*) => {
+        match $suffix {
+            $(stringify!($t) => $num.parse::<$t>().map(|v| v.to_bytes()).ok(),)*
+            _ => None,
+        }
+    };
+}
+
+/// parse_value encodes a word as an argument or a key:
+/// an integer with a type suffix such as `100i64` or `7u8`, `true` or `false`,
+/// a 0x-prefixed address of 40 hex digits or bytes of any other even number of hex digits, or a quoted string.
+pub fn parse_value(word: &Word) -> Result<Vec<u8>> {
+    if word.quoted {
+        return Ok(word.text.as_bytes().to_vec());
+    }
+    let s = word.text.as_str();
+    match s {
+        "true" => return Ok(vec![1]),
+        "false" => return Ok(vec![0]),
+        _ => {}
+    }
+    if s.starts_with("0x") || s.starts_with("0X") {
+        if s.len() == 42 {
+            return Ok(hmemu::address::from_hex(s)?.to_vec());
+        }
+        return parse_hex(&s[2..])
+            .ok_or_else(|| error::from_str(format!("invalid hex bytes `{}`", s)));
+    }
+    if let Some(pos) = s.find(|c| c == 'i' || c == 'u') {
+        let (num, suffix) = s.split_at(pos);
+        if let Some(bs) = parse_int!(num, suffix, i8, u8, i16, u16, i32, u32, i64, u64, i128, u128)
+        {
+            return Ok(bs);
+        }
+    }
+    Err(error::from_str(format!(
+        "invalid value `{}`: expected an integer with a type suffix like 100i64, true or false, 0x-prefixed hex, or a quoted string",
+        s
+    )))
+}
+
+/// parse_key encodes a word as a state key, taking an unquoted word which is not a value as a string.
+pub fn parse_key(word: &Word) -> Vec<u8> {
+    parse_value(word).unwrap_or_else(|_| word.text.as_bytes().to_vec())
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str) -> Word {
+        Word {
+            text: text.to_string(),
+            quoted: false,
+        }
+    }
+
+    #[test]
+    fn tokenize_test() {
+        assert_eq!(
+            vec![
+                word("call"),
+                word("transfer"),
+                Word {
+                    text: "a \"b\"".to_string(),
+                    quoted: true
+                },
+                word("100i64"),
+            ],
+            tokenize("  call transfer \"a \\\"b\\\"\"   100i64 ").unwrap()
+        );
+        tokenize("call \"a").expect_err("expect unterminated string");
+    }
+
+    #[test]
+    fn parse_value_test() {
+        assert_eq!(100i64.to_bytes(), parse_value(&word("100i64")).unwrap());
+        assert_eq!((-5i32).to_bytes(), parse_value(&word("-5i32")).unwrap());
+        assert_eq!(vec![1u8], parse_value(&word("true")).unwrap());
+        assert_eq!(vec![0x12u8, 0x21], parse_value(&word("0x1221")).unwrap());
+        assert_eq!(
+            20,
+            parse_value(&word("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c"))
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            b"100i64".to_vec(),
+            parse_value(&Word {
+                text: "100i64".to_string(),
+                quoted: true
+            })
+            .unwrap()
+        );
+        parse_value(&word("100")).expect_err("expect a type suffix");
+        parse_value(&word("300u8")).expect_err("expect an overflow");
+        parse_value(&word("0x123")).expect_err("expect an odd number of digits");
+
+        assert_eq!(b"balance".to_vec(), parse_key(&word("balance")));
+    }
+}
@@ -0,0 +1,442 @@
+use crate::client;
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use syn::punctuated::Punctuated;
+use syn::visit::{self, Visit};
+use syn::{
+    Expr, ExprCall, ExprMethodCall, ExprTry, GenericArgument, Ident, Item, ItemFn, ItemMod, Lit,
+    Local, Macro, Pat, PathArguments, Result, ReturnType, Token, Type,
+};
+
+// Function is an entry point of a contract module: a function annotated with `#[contract]`.
+pub struct Function {
+    pub ident: Ident,
+    pub args: Vec<Arg>,
+    pub returns: Option<Type>,
+    // exact is whether the function reads exactly `args`, and required is the number of the arguments it requires.
+    pub exact: bool,
+    pub required: usize,
+}
+
+// Arg is an argument read by `get_arg`. Its type and name are None if they can't be inferred.
+#[derive(Default)]
+pub struct Arg {
+    pub ty: Option<Type>,
+    pub name: Option<Ident>,
+}
+
+// functions returns the entry points declared in the module.
+pub fn functions(m: &ItemMod) -> Result<Vec<Function>> {
+    let items = match m.content {
+        Some((_, ref items)) => items,
+        None => {
+            return Err(syn::Error::new(
+                m.ident.span(),
+                "the module must be inline, e.g. `mod token { ... }`",
+            ))
+        }
+    };
+    let helpers = helpers(items);
+    let mut fns = Vec::new();
+    for item in items.iter() {
+        if let Item::Fn(f) = item {
+            if is_contract(f) {
+                fns.push(function(f, &helpers)?);
+            }
+        }
+    }
+    Ok(fns)
+}
+
+// helpers returns the functions of the module which are known not to read arguments,
+// so that calling them doesn't make the arguments of an entry point unknown.
+fn helpers(items: &[Item]) -> HashSet<String> {
+    let none = HashSet::new();
+    items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Fn(f) => {
+                let mut visitor = ArgVisitor::new(&none);
+                visitor.visit_block(&f.block);
+                if visitor.args.is_empty() && !visitor.unknown {
+                    Some(f.sig.ident.to_string())
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_contract(f: &ItemFn) -> bool {
+    f.attrs
+        .iter()
+        .any(|attr| match attr.path().segments.last() {
+            Some(seg) => seg.ident == "contract",
+            None => false,
+        })
+}
+
+fn function(f: &ItemFn, helpers: &HashSet<String>) -> Result<Function> {
+    let mut visitor = ArgVisitor::new(helpers);
+    visitor.visit_block(&f.block);
+    let n = visitor.args.keys().next_back().map(|i| i + 1).unwrap_or(0);
+    // the arguments are required up to the first one which isn't read by `get_arg(idx)?` or the like
+    let required = (0..n).take_while(|i| visitor.required.contains(i)).count();
+    let args = (0..n)
+        .map(|i| visitor.args.remove(&i).unwrap_or_default())
+        .collect();
+    Ok(Function {
+        ident: f.sig.ident.clone(),
+        args,
+        returns: return_type(f)?,
+        exact: required == n && !visitor.unknown,
+        required,
+    })
+}
+
+// return_type returns T of the return type `R<T>`, or None for `R<()>`.
+fn return_type(f: &ItemFn) -> Result<Option<Type>> {
+    if let ReturnType::Type(_, ref ty) = f.sig.output {
+        if let Some(Type::Tuple(t)) = generic_arg(ty, "R") {
+            if t.elems.is_empty() {
+                return Ok(None);
+            }
+        }
+        if let Some(t) = generic_arg(ty, "R") {
+            return Ok(Some(t.clone()));
+        }
+    }
+    Err(syn::Error::new(
+        f.sig.ident.span(),
+        "a contract function must return `R<T>`",
+    ))
+}
+
+// generic_arg returns T of a type `name<T>`.
+fn generic_arg<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let seg = match ty {
+        Type::Path(p) => p.path.segments.last()?,
+        _ => return None,
+    };
+    if seg.ident != name {
+        return None;
+    }
+    match seg.arguments {
+        PathArguments::AngleBracketed(ref a) => match a.args.first()? {
+            GenericArgument::Type(t) => Some(t),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// ArgVisitor collects the arguments a function reads by `get_arg`, with the types they are decoded to
+// by a turbofish `get_arg::<T>(idx)` or a typed binding `let x: T = get_arg(idx)?`.
+// The arguments are unknown if the function may read ones it doesn't read by a literal index:
+// by `get_arg` with a computed index, or in a function or a macro it can't look into.
+struct ArgVisitor<'a> {
+    args: BTreeMap<usize, Arg>,
+    required: BTreeSet<usize>,
+    unknown: bool,
+    helpers: &'a HashSet<String>,
+}
+
+impl<'a> ArgVisitor<'a> {
+    fn new(helpers: &'a HashSet<String>) -> Self {
+        ArgVisitor {
+            args: BTreeMap::new(),
+            required: BTreeSet::new(),
+            unknown: false,
+            helpers,
+        }
+    }
+}
+
+impl<'a, 'ast> Visit<'ast> for ArgVisitor<'a> {
+    fn visit_local(&mut self, local: &'ast Local) {
+        if let Some(ref init) = local.init {
+            if let Some((idx, _)) = get_arg_call(&init.expr) {
+                let arg = self.args.entry(idx).or_default();
+                let mut pat = &local.pat;
+                if let Pat::Type(ref p) = local.pat {
+                    arg.ty = Some((*p.ty).clone());
+                    pat = &p.pat;
+                }
+                if let Pat::Ident(ref p) = pat {
+                    arg.name = Some(p.ident.clone());
+                }
+            }
+        }
+        visit::visit_local(self, local);
+    }
+
+    fn visit_expr_call(&mut self, call: &'ast ExprCall) {
+        if let Some((idx, ty)) = get_arg(call) {
+            let arg = self.args.entry(idx).or_default();
+            if ty.is_some() {
+                arg.ty = ty;
+            }
+        } else if !is_known_call(call, self.helpers) {
+            self.unknown = true;
+        }
+        visit::visit_expr_call(self, call);
+    }
+
+    fn visit_expr_try(&mut self, e: &'ast ExprTry) {
+        if let Some((idx, _)) = direct_get_arg(&e.expr) {
+            self.required.insert(idx);
+        }
+        visit::visit_expr_try(self, e);
+    }
+
+    fn visit_expr_method_call(&mut self, e: &'ast ExprMethodCall) {
+        if e.method == "unwrap" || e.method == "expect" {
+            if let Some((idx, _)) = direct_get_arg(&e.receiver) {
+                self.required.insert(idx);
+            }
+        }
+        visit::visit_expr_method_call(self, e);
+    }
+
+    // a macro is looked into if its input is a list of expressions, like `format!` and `vec!`.
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        match mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated) {
+            Ok(exprs) => exprs.iter().for_each(|e| self.visit_expr(e)),
+            Err(_) => self.unknown = true,
+        }
+    }
+}
+
+// is_known_call returns whether a call is known not to read arguments, other than by a literal `get_arg(idx)`:
+// a call of the hmcdk API, std, a type or a variant, or a helper of the module.
+fn is_known_call(call: &ExprCall, helpers: &HashSet<String>) -> bool {
+    let path = match *call.func {
+        Expr::Path(ref p) if p.qself.is_none() => &p.path,
+        Expr::Path(_) => return true,
+        _ => return false,
+    };
+    // get_arg reaches here only with a computed index
+    if matches!(path.segments.last(), Some(seg) if seg.ident == "get_arg") {
+        return false;
+    }
+    let first = match path.segments.first() {
+        Some(seg) => seg.ident.to_string(),
+        None => return false,
+    };
+    if first.starts_with(|c: char| c.is_uppercase()) {
+        return true;
+    }
+    if path.segments.len() == 1 {
+        return helpers.contains(&first);
+    }
+    matches!(
+        first.as_str(),
+        "api"
+            | "hmcdk"
+            | "std"
+            | "core"
+            | "alloc"
+            | "bool"
+            | "char"
+            | "str"
+            | "u8"
+            | "u16"
+            | "u32"
+            | "u64"
+            | "u128"
+            | "usize"
+            | "i8"
+            | "i16"
+            | "i32"
+            | "i64"
+            | "i128"
+            | "isize"
+    )
+}
+
+// direct_get_arg finds a `get_arg` call which is the expression itself, e.g. the operand of `?`.
+fn direct_get_arg(expr: &Expr) -> Option<(usize, Option<Type>)> {
+    match expr {
+        Expr::Paren(e) => direct_get_arg(&e.expr),
+        Expr::Call(call) => get_arg(call),
+        _ => None,
+    }
+}
+
+// get_arg_call finds a `get_arg` call behind `?`, `unwrap()` and the like.
+fn get_arg_call(expr: &Expr) -> Option<(usize, Option<Type>)> {
+    match expr {
+        Expr::Try(e) => get_arg_call(&e.expr),
+        Expr::Paren(e) => get_arg_call(&e.expr),
+        Expr::MethodCall(e) => get_arg_call(&e.receiver),
+        Expr::Call(call) => get_arg(call),
+        _ => None,
+    }
+}
+
+fn get_arg(call: &ExprCall) -> Option<(usize, Option<Type>)> {
+    let seg = match *call.func {
+        Expr::Path(ref p) => p.path.segments.last()?,
+        _ => return None,
+    };
+    if seg.ident != "get_arg" {
+        return None;
+    }
+    let idx = match call.args.first()? {
+        Expr::Lit(e) => match e.lit {
+            Lit::Int(ref i) => i.base10_parse::<usize>().ok()?,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let ty = match seg.arguments {
+        PathArguments::AngleBracketed(ref a) => match a.args.first() {
+            Some(GenericArgument::Type(t)) => Some(t.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+    Some((idx, ty))
+}
+
+// type_name renders a type as it is written, without the spaces tokens are separated by.
+pub fn type_name(ty: &Type) -> String {
+    let s = quote!(#ty).to_string();
+    let chars: Vec<char> = s.chars().collect();
+    let mut name = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        let is_word = |c: Option<&char>| matches!(c, Some(c) if c.is_alphanumeric() || *c == '_');
+        if *c == ' ' && !(is_word(chars.get(i.wrapping_sub(1))) && is_word(chars.get(i + 1))) {
+            continue;
+        }
+        name.push(*c);
+    }
+    name
+}
+
+fn arg_type_name(arg: &Arg) -> String {
+    match arg.ty {
+        Some(ref ty) => type_name(ty),
+        None => "_".to_string(),
+    }
+}
+
+// to_json encodes the entry points in the same layout as `Abi::to_json`.
+fn to_json(fns: &[Function]) -> String {
+    let fns: Vec<String> = fns
+        .iter()
+        .map(|f| {
+            let args: Vec<String> = f
+                .args
+                .iter()
+                .map(|a| format!("\"{}\"", escape_json(&arg_type_name(a))))
+                .collect();
+            let returns = match f.returns {
+                Some(ref ty) => format!("\"{}\"", escape_json(&type_name(ty))),
+                None => "null".to_string(),
+            };
+            format!(
+                "{{\"name\":\"{}\",\"args\":[{}],\"returns\":{}}}",
+                f.ident,
+                args.join(","),
+                returns
+            )
+        })
+        .collect();
+    format!("{{\"functions\":[{}]}}", fns.join(","))
+}
+
+fn escape_json(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// signature generates the `hmemu::abi::Function` describing the entry point.
+fn signature(f: &Function) -> TokenStream {
+    let name = f.ident.to_string();
+    let args = f.args.iter().map(arg_type_name);
+    let returns = match f.returns {
+        Some(ref ty) => {
+            let ty = type_name(ty);
+            quote! { Some(#ty) }
+        }
+        None => quote! { None },
+    };
+    let arity = if f.exact {
+        quote! { ::hmemu::abi::Arity::Exact }
+    } else {
+        let n = f.required;
+        quote! { ::hmemu::abi::Arity::AtLeast(#n) }
+    };
+    quote! {
+        ::hmemu::abi::Function {
+            name: #name,
+            args: &[#(#args),*],
+            returns: #returns,
+            arity: #arity,
+        }
+    }
+}
+
+// export generates the registration of the entry point, which `hmemu::deploy_crate_contract` collects.
+fn export(f: &Function, signature: TokenStream) -> Item {
+    let name = f.ident.to_string();
+    let export = Ident::new(&format!("__{}", f.ident), Span::call_site());
+    syn::parse_quote! {
+        ::hmemu::inventory::submit! {
+            ::hmemu::ContractExport {
+                name: #name,
+                f: #export,
+                signature: Some(#signature),
+                module: module_path!(),
+            }
+        }
+    }
+}
+
+// expand_export exports a single `#[contract]` function.
+pub fn expand_export(f: ItemFn) -> Result<TokenStream> {
+    if !is_contract(&f) {
+        return Err(syn::Error::new(
+            f.sig.ident.span(),
+            "`#[hmemu::export]` must be placed on a `#[contract]` function",
+        ));
+    }
+    let function = function(&f, &HashSet::new())?;
+    let signature = signature(&function);
+    let export = export(&function, quote! { &#signature });
+    Ok(quote! {
+        #f
+        #export
+    })
+}
+
+pub fn expand(mut m: ItemMod) -> Result<TokenStream> {
+    let fns = functions(&m)?;
+    let json = to_json(&fns);
+    let entries = fns.iter().map(signature);
+    let abi: Item = syn::parse_quote! {
+        /// ABI describes the entry points of this contract.
+        pub const ABI: ::hmemu::abi::Abi = ::hmemu::abi::Abi {
+            functions: &[#(#entries),*],
+        };
+    };
+    let abi_json: Item = syn::parse_quote! {
+        /// ABI_JSON is `ABI` encoded as JSON.
+        pub const ABI_JSON: &str = #json;
+    };
+    let client = client::client(&m.ident, &fns)?;
+    let exports = fns
+        .iter()
+        .enumerate()
+        .map(|(i, f)| export(f, quote! { &ABI.functions[#i] }));
+    if let Some((_, ref mut items)) = m.content {
+        items.push(abi);
+        items.push(abi_json);
+        items.extend(client);
+        items.extend(exports);
+    }
+    Ok(quote! { #m })
+}
@@ -0,0 +1,50 @@
+use tiny_keccak::keccak256;
+
+// parse_address parses a 0x-prefixed hex address, validating the EIP-55 checksum of mixed-case input.
+pub fn parse_address(s: &str) -> Result<[u8; 20], String> {
+    let hex = if s.starts_with("0x") || s.starts_with("0X") {
+        &s[2..]
+    } else {
+        s
+    };
+    if hex.len() != 40 {
+        return Err(format!(
+            "invalid address length: expected 40 hex digits, got {}",
+            hex.len()
+        ));
+    }
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex address: {}", s));
+    }
+    let mut addr = [0u8; 20];
+    for (i, b) in addr.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("invalid hex address: {}", s))?;
+    }
+    let is_mixed_case =
+        hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    if is_mixed_case && checksum(&addr) != hex {
+        return Err(format!(
+            "invalid address checksum: expected 0x{}",
+            checksum(&addr)
+        ));
+    }
+    Ok(addr)
+}
+
+fn checksum(addr: &[u8; 20]) -> String {
+    let lower: String = addr.iter().map(|b| format!("{:02x}", b)).collect();
+    let hash = keccak256(lower.as_bytes());
+    lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (4 * (1 - i % 2))) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
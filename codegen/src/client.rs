@@ -0,0 +1,124 @@
+use crate::abi::Function;
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{Ident, Item, Result};
+
+// client_name converts the name of a contract module into the name of its client, e.g. `token` into `TokenClient`.
+pub fn client_name(module: &Ident) -> Ident {
+    let mut name = String::new();
+    for part in module.to_string().split('_').filter(|p| !p.is_empty()) {
+        let mut chars = part.chars();
+        if let Some(c) = chars.next() {
+            name.extend(c.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    name.push_str("Client");
+    Ident::new(&name, Span::call_site())
+}
+
+// method generates a typed method calling the entry point.
+// An argument whose name can't be inferred is named by its index, and one whose type can't be inferred is passed as bytes.
+fn method(f: &Function) -> TokenStream {
+    let ident = &f.ident;
+    let entry = f.ident.to_string();
+    let mut params = Vec::new();
+    let mut pushes = Vec::new();
+    for (i, arg) in f.args.iter().enumerate() {
+        let name = match arg.name {
+            Some(ref name)
+                if name != "sender" && !f.args[..i].iter().any(|a| a.name == arg.name) =>
+            {
+                Ident::new(&name.to_string(), Span::call_site())
+            }
+            _ => Ident::new(&format!("arg{}", i), Span::call_site()),
+        };
+        match arg.ty {
+            Some(ref ty) => {
+                params.push(quote! { #name: #ty });
+                pushes.push(quote! { .arg(#name) });
+            }
+            None => {
+                params.push(quote! { #name: &[u8] });
+                pushes.push(quote! { .arg_bytes(#name) });
+            }
+        }
+    }
+    let doc = format!("{} calls the entry point `{}` as `sender`.", entry, entry);
+    let (returns, value) = match f.returns {
+        Some(ref ty) => (
+            quote! { ::hmemu::Result<Option<#ty>> },
+            quote! { ::hmemu::invoke_value::<#ty>(sender, &self.address, #entry, args) },
+        ),
+        None => (
+            quote! { ::hmemu::Result<()> },
+            quote! { ::hmemu::invoke_value::<Vec<u8>>(sender, &self.address, #entry, args).map(|_| ()) },
+        ),
+    };
+    quote! {
+        #[doc = #doc]
+        pub fn #ident(&self, sender: &[u8; 20], #(#params),*) -> #returns {
+            let args = ::hmemu::types::ArgsBuilder::new()#(#pushes)*.into();
+            #value
+        }
+    }
+}
+
+// CONSTRUCTORS are the methods of a client which an entry point can't be named after.
+const CONSTRUCTORS: &[&str] = &["new", "deploy"];
+
+// client generates the client of a contract module, which calls its entry points through the emulator.
+pub fn client(module: &Ident, fns: &[Function]) -> Result<Vec<Item>> {
+    let name = client_name(module);
+    if let Some(f) = fns
+        .iter()
+        .find(|f| CONSTRUCTORS.iter().any(|c| f.ident == c))
+    {
+        return Err(syn::Error::new(
+            f.ident.span(),
+            format!(
+                "the entry point `{}` collides with the constructor `{}::{}`, rename the function",
+                f.ident, name, f.ident
+            ),
+        ));
+    }
+    let doc = format!(
+        "{} calls the entry points of `{}` deployed at an address.",
+        name, module
+    );
+    let methods = fns.iter().map(method);
+    let entries = fns.iter().map(|f| f.ident.to_string());
+    let indices = 0..fns.len();
+    let exports = fns
+        .iter()
+        .map(|f| Ident::new(&format!("__{}", f.ident), Span::call_site()));
+    Ok(vec![
+        syn::parse_quote! {
+            #[doc = #doc]
+            #[derive(Debug, Clone, Copy, PartialEq)]
+            pub struct #name {
+                pub address: [u8; 20],
+            }
+        },
+        syn::parse_quote! {
+            impl #name {
+                pub fn new(address: [u8; 20]) -> Self {
+                    #name { address }
+                }
+
+                /// deploy registers the entry points at the address with their signatures in `ABI`, and returns a client of them.
+                pub fn deploy(address: [u8; 20]) -> Self {
+                    #(::hmemu::register_contract_function_with_signature(
+                        address,
+                        #entries.to_string(),
+                        #exports,
+                        &ABI.functions[#indices],
+                    );)*
+                    #name { address }
+                }
+
+                #(#methods)*
+            }
+        },
+    ])
+}
@@ -0,0 +1,35 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Ident, Path, Result};
+
+// expand converts a path of a `#[contract]` function into the path of the contract API it exports,
+// e.g. `token::transfer` into `token::__transfer`, and evaluates to it as a `fn() -> i32`.
+pub fn expand(mut path: Path) -> Result<TokenStream> {
+    let seg = match path.segments.last_mut() {
+        Some(seg) => seg,
+        None => return Err(syn::Error::new_spanned(&path, "expected a function path")),
+    };
+    if seg.ident == "self" || seg.ident == "super" || seg.ident == "crate" {
+        return Err(syn::Error::new(
+            seg.ident.span(),
+            format!(
+                "expected a function path, e.g. `{}::transfer`, found a module",
+                seg.ident
+            ),
+        ));
+    }
+    if !seg.arguments.is_none() {
+        return Err(syn::Error::new_spanned(
+            &seg.arguments,
+            "a contract function can't take generic arguments",
+        ));
+    }
+    // convert into contract API
+    seg.ident = Ident::new(&format!("__{}", seg.ident), seg.ident.span());
+    Ok(quote! {
+        {
+            let f: fn() -> i32 = #path;
+            f
+        }
+    })
+}
@@ -1,40 +1,120 @@
 extern crate proc_macro;
 use crate::proc_macro::TokenStream;
 use quote::quote;
-use syn;
-use syn::{parse_macro_input, FnArg};
+use syn::{parse_macro_input, ItemFn, ItemMod, LitStr, Path};
 
+mod abi;
+mod address;
+mod client;
+mod contract_fn;
+mod test_attr;
+
+/// lookup_contract_fn_impl resolves the path of a `#[contract]` function into the contract API it exports,
+/// e.g. `token::transfer` into `token::__transfer`. Use it through `hmemu::contract_fn!`.
 #[proc_macro]
 pub fn lookup_contract_fn_impl(input: TokenStream) -> TokenStream {
-    let item = process_input(input.clone());
-    let ast = parse_macro_input!(item as FnArg);
+    let path = parse_macro_input!(input as Path);
+    let t: TokenStream = match contract_fn::expand(path) {
+        Ok(q) => q,
+        Err(e) => e.to_compile_error(),
+    }
+    .into();
+    t
+}
 
-    let q = quote! {
-        #[inline]
-        fn output() -> fn() -> i32 {
-            #ast
+/// address parses a hex string into an `Address` at compile time.
+/// A mixed-case string must carry a valid EIP-55 checksum.
+///
+/// ```ignore
+/// const ALICE: Address = address!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+/// ```
+#[proc_macro]
+pub fn address(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    let t: TokenStream = match address::parse_address(&lit.value()) {
+        Ok(addr) => {
+            let bytes = addr.iter();
+            quote! {
+                [#(#bytes),*]
+            }
         }
-    };
-    let t: TokenStream = q.into();
+        Err(e) => syn::Error::new(lit.span(), e).to_compile_error(),
+    }
+    .into();
     t
 }
 
-fn process_input(input: TokenStream) -> TokenStream {
-    let ast = parse_macro_input!(input as FnArg);
-    let path = match ast {
-        syn::FnArg::Ignored(syn::Type::Path(mut x)) => {
-            let id = x.path.segments.len();
-            let mut seg = &mut x.path.segments[id - 1];
-            // convert into contract API
-            let ident = format!("__{}", seg.ident);
-            seg.ident = syn::Ident::new(&ident, seg.ident.span());
-            x
-        }
-        _ => panic!("failed!"),
-    };
-    let gen = quote! {
-        #path
-    };
-    let t: TokenStream = gen.into();
+/// test runs the annotated function as a test inside a fresh process.
+/// The body is executed as a contract call of `sender` with `args` and may use `?`.
+/// A function without a return type returns early with `return Ok(())`.
+/// The function can't be `async`, generic or `pub`, nor take arguments.
+///
+/// ```ignore
+/// #[hmemu::test(sender = "0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c", args = [1i64, 2i64])]
+/// fn add_test() {
+///     assert_eq!(Some(3), add()?);
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let ta = parse_macro_input!(attr as test_attr::TestArgs);
+    let f = parse_macro_input!(item as ItemFn);
+    let t: TokenStream = match test_attr::expand(ta, f) {
+        Ok(q) => q,
+        Err(e) => e.to_compile_error(),
+    }
+    .into();
+    t
+}
+
+/// abi generates the ABI of the `#[contract]` functions in the annotated module,
+/// as `ABI: hmemu::abi::Abi` and `ABI_JSON: &str` appended to the module,
+/// and a client with a typed method per function, e.g. `TokenClient` for a module `token`.
+/// The functions are also exported to `hmemu::deploy_crate_contract`.
+/// Argument types are taken from `get_arg::<T>(idx)` or `let x: T = get_arg(idx)?`, and the return type from `R<T>`.
+/// The number of arguments is exact only if every argument is read by a required `get_arg(idx)?` in the function itself;
+/// otherwise, e.g. when an argument is read by a helper or is optional, the function takes at least the required ones.
+///
+/// ```ignore
+/// #[hmemu::abi]
+/// pub mod token {
+///     #[contract]
+///     pub fn transfer() -> R<u64> {
+///         let to: Address = api::get_arg(0)?;
+///         let amount: u64 = api::get_arg(1)?;
+///         ...
+///     }
+/// }
+/// assert_eq!("transfer(Address, u64) -> u64", token::ABI.functions[0].to_string());
+///
+/// let client = token::TokenClient::deploy(TOKEN);
+/// let balance: Option<u64> = client.transfer(&SENDER, to, 10)?;
+/// ```
+#[proc_macro_attribute]
+pub fn abi(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let m = parse_macro_input!(item as ItemMod);
+    let t: TokenStream = match abi::expand(m) {
+        Ok(q) => q,
+        Err(e) => e.to_compile_error(),
+    }
+    .into();
+    t
+}
+
+/// export exports a `#[contract]` function with its signature, so that `hmemu::deploy_crate_contract` registers it.
+///
+/// ```ignore
+/// #[hmemu::export]
+/// #[contract]
+/// pub fn init() -> R<()> { ... }
+/// ```
+#[proc_macro_attribute]
+pub fn export(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let f = parse_macro_input!(item as ItemFn);
+    let t: TokenStream = match abi::expand_export(f) {
+        Ok(q) => q,
+        Err(e) => e.to_compile_error(),
+    }
+    .into();
     t
 }
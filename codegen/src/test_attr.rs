@@ -0,0 +1,177 @@
+use crate::address::parse_address;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream, Result};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{
+    bracketed, Block, Expr, ExprAsync, ExprClosure, ExprReturn, Ident, Item, ItemFn, LitStr,
+    ReturnType, Token, Visibility,
+};
+
+// TestArgs is the argument list of `#[hmemu::test(...)]`.
+#[derive(Default)]
+pub struct TestArgs {
+    sender: Option<LitStr>,
+    contract: Option<LitStr>,
+    args: Vec<Expr>,
+}
+
+impl Parse for TestArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut ta = TestArgs::default();
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            match key.to_string().as_str() {
+                "sender" => ta.sender = Some(input.parse()?),
+                "contract" => ta.contract = Some(input.parse()?),
+                "args" => {
+                    let content;
+                    bracketed!(content in input);
+                    let args: Punctuated<Expr, Token![,]> =
+                        content.parse_terminated(Expr::parse, Token![,])?;
+                    ta.args = args.into_iter().collect();
+                }
+                other => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!(
+                            "unknown argument `{}`, expected `sender`, `contract` or `args`",
+                            other
+                        ),
+                    ))
+                }
+            }
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        Ok(ta)
+    }
+}
+
+fn address_tokens(lit: &LitStr) -> Result<TokenStream> {
+    let addr = parse_address(&lit.value()).map_err(|e| syn::Error::new(lit.span(), e))?;
+    let bytes = addr.iter();
+    Ok(quote! { [#(#bytes),*] })
+}
+
+// check_signature rejects what a test function can't have, as the body is run in a closure by a generated test.
+fn check_signature(f: &ItemFn) -> Result<()> {
+    let sig = &f.sig;
+    if let Some(ref asyncness) = sig.asyncness {
+        return Err(syn::Error::new(
+            asyncness.span(),
+            "`#[hmemu::test]` doesn't support `async` functions",
+        ));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "a `#[hmemu::test]` function can't be generic",
+        ));
+    }
+    if !sig.inputs.is_empty() {
+        return Err(syn::Error::new(
+            sig.inputs.span(),
+            "a `#[hmemu::test]` function can't take arguments, use `args = [..]` instead",
+        ));
+    }
+    if let Visibility::Inherited = f.vis {
+        Ok(())
+    } else {
+        Err(syn::Error::new(
+            f.vis.span(),
+            "a `#[hmemu::test]` function can't have a visibility",
+        ))
+    }
+}
+
+// ReturnVisitor finds a bare `return` of the function, which is not valid in the closure
+// a test without a return type is run in. Returns of nested closures, async blocks and items are skipped.
+#[derive(Default)]
+struct ReturnVisitor {
+    bare: Option<ExprReturn>,
+}
+
+impl<'ast> Visit<'ast> for ReturnVisitor {
+    fn visit_expr_return(&mut self, e: &'ast ExprReturn) {
+        if e.expr.is_none() && self.bare.is_none() {
+            self.bare = Some(e.clone());
+        }
+        visit::visit_expr_return(self, e);
+    }
+
+    fn visit_expr_closure(&mut self, _: &'ast ExprClosure) {}
+
+    fn visit_expr_async(&mut self, _: &'ast ExprAsync) {}
+
+    fn visit_item(&mut self, _: &'ast Item) {}
+}
+
+fn check_returns(block: &Block) -> Result<()> {
+    let mut visitor = ReturnVisitor::default();
+    visitor.visit_block(block);
+    match visitor.bare {
+        Some(e) => Err(syn::Error::new(
+            e.span(),
+            "a `#[hmemu::test]` function without a return type must return with `return Ok(())`",
+        )),
+        None => Ok(()),
+    }
+}
+
+pub fn expand(ta: TestArgs, f: ItemFn) -> Result<TokenStream> {
+    check_signature(&f)?;
+    let sender = match ta.sender {
+        Some(ref lit) => address_tokens(lit)?,
+        None => quote! { [0u8; 20] },
+    };
+    let init_contract = match ta.contract {
+        Some(ref lit) => {
+            let contract = address_tokens(lit)?;
+            quote! { ::hmemu::init_contract_address(&#contract)?; }
+        }
+        None => quote! {},
+    };
+    let args = ta.args;
+    // the generated function is a test already
+    let attrs = f.attrs.iter().filter(|attr| !attr.path().is_ident("test"));
+    let ident = &f.sig.ident;
+    let block = &f.block;
+    // a test without a return type gets an implicit `Ok(())` so that `?` can be used in its body,
+    // so it returns early with `return Ok(())`
+    let body = match f.sig.output {
+        ReturnType::Default => {
+            check_returns(block)?;
+            quote! {
+                || -> ::hmemu::Result<()> {
+                    #block;
+                    Ok(())
+                }
+            }
+        }
+        ReturnType::Type(_, ref ty) => quote! {
+            || -> #ty #block
+        },
+    };
+
+    Ok(quote! {
+        #[test]
+        #(#attrs)*
+        fn #ident() {
+            let __hmemu_sender: [u8; 20] = #sender;
+            let __hmemu_args = ::hmemu::args![#(#args),*];
+            let __hmemu_res = ::hmemu::run_process(move || {
+                #init_contract
+                ::hmemu::call_contract(&__hmemu_sender, __hmemu_args, #body)
+            });
+            if let Err(e) = __hmemu_res {
+                panic!("{} failed: {:?}", stringify!(#ident), e);
+            }
+        }
+    })
+}
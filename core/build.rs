@@ -3,6 +3,11 @@ use std::path::Path;
 use std::process::Command;
 
 fn main() {
+    // The native backend implements the host functions in Rust, so the Go runtime isn't needed.
+    if env::var("CARGO_FEATURE_BACKEND_NATIVE").is_ok() {
+        return;
+    }
+
     // If the package has a build script, this is set to the folder where the build script should place its output.
     let out_dir = env::var("OUT_DIR").unwrap();
 
@@ -0,0 +1,283 @@
+//! ABI of a contract, generated from its `#[contract]` functions by `#[hmemu::abi]`.
+use crate::address::to_checksum_hex;
+use crate::process::Result;
+use crate::state::display_bytes;
+use crate::trace::escape_json;
+use hmcdk::error;
+use hmcdk::prelude::*;
+use std::fmt;
+
+/// Abi describes the entry points of a contract.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Abi {
+    pub functions: &'static [Function],
+}
+
+/// Function describes an entry point: the types its arguments are decoded to by `get_arg`,
+/// and the type it returns. `_` is the type of an argument whose type could not be inferred.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Function {
+    pub name: &'static str,
+    pub args: &'static [&'static str],
+    pub returns: Option<&'static str>,
+    pub arity: Arity,
+}
+
+/// Arity is the number of arguments an entry point takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    /// Exact is exactly the arguments in `args`.
+    Exact,
+    /// AtLeast is at least the number of arguments, which is less than or equal to the length of `args`.
+    /// It is used when the arguments can't all be inferred, e.g. when an argument is read by a helper
+    /// or is optional like `get_arg(2).unwrap_or(0)`.
+    AtLeast(usize),
+}
+
+impl Abi {
+    /// function returns the entry point with the name.
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// to_json encodes the ABI as `{"functions":[{"name":..,"args":[..],"returns":..}]}`.
+    pub fn to_json(&self) -> String {
+        let fns: Vec<String> = self.functions.iter().map(|f| f.to_json()).collect();
+        format!("{{\"functions\":[{}]}}", fns.join(","))
+    }
+}
+
+impl Function {
+    pub fn to_json(&self) -> String {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|a| format!("\"{}\"", escape_json(a)))
+            .collect();
+        let returns = match self.returns {
+            Some(ty) => format!("\"{}\"", escape_json(ty)),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"name\":\"{}\",\"args\":[{}],\"returns\":{}}}",
+            escape_json(self.name),
+            args.join(","),
+            returns
+        )
+    }
+
+    /// validate checks the number of the arguments against the arity and the width of every argument of a fixed-size type,
+    /// and fails with the offending argument, e.g. `transfer(Address, u64): argument 1 (u64) must be 8 bytes, got 4`.
+    pub fn validate<A: AsRef<[u8]>>(&self, args: &[A]) -> Result<()> {
+        match self.arity {
+            Arity::Exact if args.len() != self.args.len() => {
+                return Err(error::from_str(format!(
+                    "{}: expected {} arguments, got {}",
+                    self.signature(),
+                    self.args.len(),
+                    args.len()
+                )));
+            }
+            Arity::AtLeast(n) if args.len() < n => {
+                return Err(error::from_str(format!(
+                    "{}: expected at least {} arguments, got {}",
+                    self.signature(),
+                    n,
+                    args.len()
+                )));
+            }
+            _ => {}
+        }
+        for (i, (ty, arg)) in self.args.iter().zip(args.iter()).enumerate() {
+            if let Some(width) = width(ty) {
+                if arg.as_ref().len() != width {
+                    return Err(error::from_str(format!(
+                        "{}: argument {} ({}) must be {} bytes, got {}",
+                        self.signature(),
+                        i,
+                        ty,
+                        width,
+                        arg.as_ref().len()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // signature renders the function without its return type, e.g. `transfer(Address, u64)`,
+    // or `transfer(Address, u64, ..)` if it may take more arguments.
+    fn signature(&self) -> String {
+        let mut args = self.args.to_vec();
+        if let Arity::AtLeast(_) = self.arity {
+            args.push("..");
+        }
+        format!("{}({})", self.name, args.join(", "))
+    }
+
+    /// format_call renders a call of the function with the arguments decoded by their types,
+    /// e.g. `transfer(0x1221a0726d56aEdeA9dBe2522DdAE3Dd8ED0f36c, 100)`.
+    pub fn format_call(&self, args: &[Vec<u8>]) -> String {
+        let args: Vec<String> = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| format_value(self.args.get(i).cloned().unwrap_or("_"), arg))
+            .collect();
+        format!("{}({})", self.name, args.join(", "))
+    }
+
+    /// format_return renders a return value decoded by the return type.
+    pub fn format_return(&self, value: &[u8]) -> String {
+        format_value(self.returns.unwrap_or("_"), value)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.signature())?;
+        if let Some(ty) = self.returns {
+            write!(f, " -> {}", ty)?;
+        }
+        Ok(())
+    }
+}
+
+/// width returns the number of bytes a value of the type named by the ABI is encoded to, if it is fixed.
+pub fn width(ty: &str) -> Option<usize> {
+    match ty {
+        "i8" | "u8" | "bool" => Some(1),
+        "i16" | "u16" => Some(2),
+        "i32" | "u32" => Some(4),
+        "i64" | "u64" => Some(8),
+        "i128" | "u128" => Some(16),
+        "Address" => Some(20),
+        _ => None,
+    }
+}
+
+macro_rules! format_num {
+    ($ty:expr, $bs:expr, $($t:ty),*) => {
+        match $ty {
+            $(stringify!($t) => return <$t>::from_bytes($bs.to_vec()).ok().map(|v| v.to_string()),)*
+            _ => {}
+        }
+    };
+}
+
+/// format_value renders bytes decoded as the type named by the ABI.
+/// Bytes which are not a valid encoding of the type are rendered as they are.
+pub fn format_value(ty: &str, bs: &[u8]) -> String {
+    decode_value(ty, bs).unwrap_or_else(|| display_bytes(bs))
+}
+
+fn decode_value(ty: &str, bs: &[u8]) -> Option<String> {
+    format_num!(ty, bs, i8, u8, i16, u16, i32, u32, i64, u64, i128, u128);
+    match ty {
+        "Address" => Address::from_bytes(bs.to_vec())
+            .ok()
+            .map(|a| to_checksum_hex(&a)),
+        "bool" if bs.len() == 1 => Some((bs[0] == 1).to_string()),
+        "String" | "&str" => String::from_utf8(bs.to_vec())
+            .ok()
+            .map(|s| format!("{:?}", s)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmemu_codegen::address;
+
+    const ABI: Abi = Abi {
+        functions: &[
+            Function {
+                name: "transfer",
+                args: &["Address", "u64"],
+                returns: Some("u64"),
+                arity: Arity::Exact,
+            },
+            Function {
+                name: "init",
+                args: &[],
+                returns: None,
+                arity: Arity::Exact,
+            },
+        ],
+    };
+
+    #[test]
+    fn abi_test() {
+        let transfer = ABI.function("transfer").unwrap();
+        assert_eq!("transfer(Address, u64) -> u64", transfer.to_string());
+        assert_eq!("init()", ABI.function("init").unwrap().to_string());
+        assert!(ABI.function("mint").is_none());
+        assert_eq!(
+            r#"{"functions":[{"name":"transfer","args":["Address","u64"],"returns":"u64"},{"name":"init","args":[],"returns":null}]}"#,
+            ABI.to_json()
+        );
+
+        let to = address!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(
+            "transfer(0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed, 100)",
+            transfer.format_call(&crate::args![to, 100u64])
+        );
+        // a malformed or extra argument is rendered as bytes
+        assert_eq!(
+            "transfer(0x01, 100, \"x\")",
+            transfer.format_call(&crate::args![vec![1u8], 100u64, "x"])
+        );
+        assert_eq!("7", transfer.format_return(&7u64.to_bytes()));
+    }
+
+    #[test]
+    fn validate_test() {
+        let transfer = ABI.function("transfer").unwrap();
+        let to = address!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        transfer.validate(&crate::args![to, 100u64]).unwrap();
+        assert_eq!(
+            "transfer(Address, u64): expected 2 arguments, got 1",
+            transfer
+                .validate(&crate::args![to])
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "transfer(Address, u64): argument 1 (u64) must be 8 bytes, got 4",
+            transfer
+                .validate(&crate::args![to, 100i32])
+                .unwrap_err()
+                .to_string()
+        );
+
+        // the width of an argument of a variable-size type is not checked
+        let f = Function {
+            name: "set",
+            args: &["String", "_"],
+            returns: None,
+            arity: Arity::Exact,
+        };
+        f.validate(&crate::args!["key", vec![1u8, 2, 3]]).unwrap();
+
+        // a function which may take more arguments than it is known to read only needs the required ones
+        let f = Function {
+            name: "mint",
+            args: &["u64", "u64"],
+            returns: Some("u64"),
+            arity: Arity::AtLeast(1),
+        };
+        f.validate(&crate::args![1u64]).unwrap();
+        f.validate(&crate::args![1u64, 2u64, "memo"]).unwrap();
+        assert_eq!(
+            "mint(u64, u64, ..): expected at least 1 arguments, got 0",
+            f.validate(&crate::args![]).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "mint(u64, u64, ..): argument 1 (u64) must be 8 bytes, got 4",
+            f.validate(&crate::args![1u64, 2u32])
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!("mint(u64, u64, ..) -> u64", f.to_string());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::process::Result;
+use hmcdk::error;
+use hmcdk::prelude::*;
+use secp256k1::{Message, PublicKey, SecretKey};
+use tiny_keccak::keccak256;
+
+/// Account is a secp256k1 keypair for producing signatures in tests.
+pub struct Account {
+    secret: SecretKey,
+    public: PublicKey,
+}
+
+impl Account {
+    /// from_seed derives a keypair deterministically, so the same seed always gives the same address.
+    pub fn from_seed(seed: u64) -> Self {
+        let mut key = keccak256(&seed.to_be_bytes());
+        loop {
+            // a hash is almost always a valid secret key, rehash on the rare miss
+            if let Ok(account) = Account::from_secret_key(&key) {
+                return account;
+            }
+            key = keccak256(&key);
+        }
+    }
+
+    pub fn from_secret_key(key: &[u8; 32]) -> Result<Self> {
+        let secret = SecretKey::parse(key)
+            .map_err(|e| error::from_str(format!("invalid secret key: {:?}", e)))?;
+        let public = PublicKey::from_secret_key(&secret);
+        Ok(Account { secret, public })
+    }
+
+    pub fn secret_key(&self) -> [u8; 32] {
+        self.secret.serialize()
+    }
+
+    /// public_key returns the uncompressed public key, the same format `api::ecrecover` returns.
+    pub fn public_key(&self) -> [u8; 65] {
+        self.public.serialize()
+    }
+
+    pub fn address(&self) -> Address {
+        pubkey_to_address(&self.public_key())
+    }
+
+    /// sign returns (v, r, s) for hash. v is the recovery id plus 27 as with ethereum's ecrecover.
+    pub fn sign(&self, hash: &[u8; 32]) -> (u8, [u8; 32], [u8; 32]) {
+        let (sig, recid) = secp256k1::sign(&Message::parse(hash), &self.secret);
+        let sig = sig.serialize();
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+        r.copy_from_slice(&sig[..32]);
+        s.copy_from_slice(&sig[32..]);
+        (recid.serialize() + 27, r, s)
+    }
+}
+
+/// pubkey_to_address converts an uncompressed public key into an address.
+pub fn pubkey_to_address(pubkey: &[u8; 65]) -> Address {
+    let h = keccak256(&pubkey[1..]);
+    let mut addr: Address = Default::default();
+    addr.copy_from_slice(&h[12..]);
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::exec_process;
+    use hmcdk::api;
+
+    #[test]
+    fn from_seed_test() {
+        assert_eq!(
+            Account::from_seed(1).address(),
+            Account::from_seed(1).address()
+        );
+        assert_ne!(
+            Account::from_seed(1).address(),
+            Account::from_seed(2).address()
+        );
+    }
+
+    #[test]
+    fn ecrecover_test() {
+        let account = Account::from_seed(1);
+        let hash = keccak256("message".as_bytes());
+        let (v, r, s) = account.sign(&hash);
+        exec_process(|| {
+            let addr = api::ecrecover_address(&hash, &[v], &r, &s)?;
+            assert_eq!(account.address(), addr);
+            let pubkey = api::ecrecover(&hash, &[v], &r, &s)?;
+            assert_eq!(&account.public_key()[..], &pubkey[..]);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+use crate::process::Result;
+use hmcdk::error;
+use hmcdk::prelude::*;
+use tiny_keccak::keccak256;
+
+/// from_hex parses a 0x-prefixed hex string into an address.
+/// A mixed-case string must carry a valid EIP-55 checksum.
+pub fn from_hex(s: &str) -> Result<Address> {
+    let hex = if s.starts_with("0x") || s.starts_with("0X") {
+        &s[2..]
+    } else {
+        s
+    };
+    if hex.len() != 40 {
+        return Err(error::from_str(format!(
+            "invalid address length: {}",
+            hex.len()
+        )));
+    }
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(error::from_str(format!("invalid hex address: {}", s)));
+    }
+    let mut addr: Address = Default::default();
+    for (i, b) in addr.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| error::from_str(format!("invalid hex address: {}", s)))?;
+    }
+    let is_mixed_case =
+        hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    if is_mixed_case && to_checksum_hex(&addr)[2..] != *hex {
+        return Err(error::from_str(format!("invalid address checksum: {}", s)));
+    }
+    Ok(addr)
+}
+
+/// to_hex returns a lowercase 0x-prefixed hex string.
+pub fn to_hex(addr: &Address) -> String {
+    let mut s = String::from("0x");
+    for b in addr.iter() {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+/// to_checksum_hex returns a 0x-prefixed hex string in EIP-55 mixed case.
+pub fn to_checksum_hex(addr: &Address) -> String {
+    let lower = to_hex(addr);
+    let hash = keccak256(&lower.as_bytes()[2..]);
+    let mut s = String::from("0x");
+    for (i, c) in lower[2..].chars().enumerate() {
+        let nibble = if i % 2 == 0 {
+            hash[i / 2] >> 4
+        } else {
+            hash[i / 2] & 0x0f
+        };
+        if nibble >= 8 {
+            s.push(c.to_ascii_uppercase());
+        } else {
+            s.push(c);
+        }
+    }
+    s
+}
+
+/// address_from_label derives a deterministic address from a human readable label such as "alice".
+pub fn address_from_label(label: &str) -> Address {
+    let h = keccak256(label.as_bytes());
+    let mut addr: Address = Default::default();
+    addr.copy_from_slice(&h[12..]);
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmemu_codegen::address;
+
+    #[test]
+    fn checksum_test() {
+        for s in &[
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let addr = from_hex(s).unwrap();
+            assert_eq!(*s, to_checksum_hex(&addr));
+            assert_eq!(s.to_lowercase(), to_hex(&addr));
+            assert_eq!(addr, from_hex(&s.to_lowercase()).unwrap());
+        }
+        from_hex("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").expect_err("expect error");
+        from_hex("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beae").expect_err("expect error");
+        from_hex("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaeg").expect_err("expect error");
+        // 40 bytes, but not 40 hex digits
+        from_hex("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beéd").expect_err("expect error");
+        from_hex("0x+aaeb6053f3e94c9b9a09f33669435e7ef1beaed").expect_err("expect error");
+    }
+
+    #[test]
+    fn address_macro_test() {
+        const ADDR: Address = address!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(
+            from_hex("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").unwrap(),
+            ADDR
+        );
+    }
+
+    #[test]
+    fn label_test() {
+        assert_eq!(address_from_label("alice"), address_from_label("alice"));
+        assert_ne!(address_from_label("alice"), address_from_label("bob"));
+    }
+}
@@ -0,0 +1,373 @@
+//! Conformance scenarios every backend must pass with identical observations.
+//!
+//! A backend is selected at compile time, so the suite runs once per backend:
+//! `cargo test -p core` checks the native backend and
+//! `cargo test -p core --no-default-features` checks the Go runtime.
+//! Each scenario pins down exact observable values, so a behavioural difference between backends fails one of the runs.
+use crate::args;
+use crate::process::*;
+use hmcdk::api;
+use hmcdk::prelude::*;
+use hmemu_codegen::address;
+
+const SENDER: Address = address!("0x0000000000000000000000000000000000000001");
+const CONTRACT_A: Address = address!("0x00000000000000000000000000000000000000aa");
+const CONTRACT_B: Address = address!("0x00000000000000000000000000000000000000bb");
+const CONTRACT_C: Address = address!("0x00000000000000000000000000000000000000cc");
+
+// value_of_size returns a value whose bytes differ by position, so truncation and reordering are detected.
+fn value_of_size(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
+
+const SIZES: [usize; 6] = [0, 1, BUF_SIZE - 1, BUF_SIZE, BUF_SIZE + 1, BUF_SIZE * 3];
+
+#[test]
+fn argument_passing() {
+    let mut args = args![1i32, -2i64, SENDER, "name".to_string()];
+    for size in SIZES.iter() {
+        args.push(value_of_size(*size));
+    }
+    let expected = args.clone();
+
+    exec_process_with_arguments(args, || {
+        assert_eq!(1i32, api::get_arg::<i32>(0)?);
+        assert_eq!(-2i64, api::get_arg::<i64>(1)?);
+        assert_eq!(SENDER, api::get_arg::<Address>(2)?);
+        assert_eq!("name", api::get_arg::<String>(3)?);
+        for (i, arg) in expected.iter().enumerate() {
+            assert_eq!(arg, &api::get_arg::<Vec<u8>>(i)?);
+        }
+        api::get_arg::<Vec<u8>>(expected.len()).expect_err("expect error");
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn nested_call_arguments() {
+    fn echo() -> i32 {
+        let mut v: Vec<u8> = Vec::new();
+        let mut i = 0;
+        while let Ok(arg) = api::get_arg::<Vec<u8>>(i) {
+            v.extend_from_slice(&(arg.len() as u32).to_be_bytes());
+            v.extend_from_slice(&arg);
+            i += 1;
+        }
+        api::return_value(&v)
+    }
+
+    run_process(|| {
+        register_contract_function(CONTRACT_B, "echo".to_string(), echo);
+        call_contract(&SENDER, args![], || {
+            let large = value_of_size(BUF_SIZE * 2 + 1);
+            let res: Vec<u8> =
+                api::call_contract(&CONTRACT_B, "echo".as_bytes(), vec![&[], &[1u8], &large])?;
+            let mut expected = Vec::new();
+            for arg in [&[][..], &[1u8][..], &large[..]].iter() {
+                expected.extend_from_slice(&(arg.len() as u32).to_be_bytes());
+                expected.extend_from_slice(arg);
+            }
+            assert_eq!(expected, res);
+            // arguments of the caller are restored after the nested call returns
+            api::get_arg::<Vec<u8>>(0).expect_err("expect error");
+            Ok(())
+        })
+    })
+    .unwrap();
+}
+
+#[test]
+fn sender_switching() {
+    fn func_a() -> i32 {
+        assert_eq!(SENDER, api::get_sender().unwrap());
+        assert_eq!(CONTRACT_A, api::get_contract_address().unwrap());
+        let res: Vec<u8> = api::call_contract(&CONTRACT_B, "func_b".as_bytes(), vec![]).unwrap();
+        assert_eq!(SENDER, api::get_sender().unwrap());
+        assert_eq!(CONTRACT_A, api::get_contract_address().unwrap());
+        api::return_value(&res)
+    }
+    fn func_b() -> i32 {
+        assert_eq!(CONTRACT_A, api::get_sender().unwrap());
+        assert_eq!(CONTRACT_B, api::get_contract_address().unwrap());
+        let mut res: Vec<u8> =
+            api::call_contract(&CONTRACT_C, "func_c".as_bytes(), vec![]).unwrap();
+        assert_eq!(CONTRACT_A, api::get_sender().unwrap());
+        assert_eq!(CONTRACT_B, api::get_contract_address().unwrap());
+        res.extend_from_slice(&api::get_sender().unwrap());
+        api::return_value(&res)
+    }
+    fn func_c() -> i32 {
+        assert_eq!(CONTRACT_C, api::get_contract_address().unwrap());
+        api::return_value(&api::get_sender().unwrap())
+    }
+
+    run_process(|| {
+        init_contract_address(&CONTRACT_A)?;
+        register_contract_function(CONTRACT_B, "func_b".to_string(), func_b);
+        register_contract_function(CONTRACT_C, "func_c".to_string(), func_c);
+        call_contract(&SENDER, args![], || {
+            func_a();
+            Ok(())
+        })?;
+        let ret: Vec<u8> = get_return_value()?;
+        assert_eq!([CONTRACT_B, CONTRACT_A].concat(), ret);
+
+        // the sender is reset between transactions
+        call_contract(&CONTRACT_C, args![], || {
+            assert_eq!(CONTRACT_C, api::get_sender()?);
+            Ok(())
+        })
+    })
+    .unwrap();
+}
+
+#[test]
+fn state_isolation() {
+    let key = "key".as_bytes();
+    fn write_b() -> i32 {
+        api::write_state("key".as_bytes(), "value_b".as_bytes());
+        api::return_value(&[])
+    }
+    fn read_b() -> i32 {
+        let v: Vec<u8> = api::read_state("key".as_bytes()).unwrap_or_default();
+        api::return_value(&v)
+    }
+
+    run_process(|| {
+        init_contract_address(&CONTRACT_A)?;
+        register_contract_function(CONTRACT_B, "write_b".to_string(), write_b);
+        register_contract_function(CONTRACT_B, "read_b".to_string(), read_b);
+
+        call_contract(&SENDER, args![], || {
+            api::write_state(key, "value_a".as_bytes());
+            let _: Vec<u8> = api::call_contract(&CONTRACT_B, "write_b".as_bytes(), vec![])?;
+            // writes are not visible before they are committed
+            api::read_state::<Vec<u8>>(key).expect_err("expect error");
+            Ok(())
+        })?;
+
+        assert_eq!("value_a", api::read_state::<String>(key)?);
+        call_contract(&SENDER, args![], || {
+            let v: String = api::call_contract(&CONTRACT_B, "read_b".as_bytes(), vec![])?;
+            assert_eq!("value_b", v);
+            Ok(())
+        })?;
+
+        init_contract_address(&CONTRACT_B)?;
+        assert_eq!("value_b", api::read_state::<String>(key)?);
+        init_contract_address(&CONTRACT_C)?;
+        api::read_state::<Vec<u8>>(key).expect_err("expect error");
+
+        // a failed transaction isn't committed
+        init_contract_address(&CONTRACT_A)?;
+        let res: Result<()> = call_contract(&SENDER, args![], || {
+            api::write_state("other".as_bytes(), "value".as_bytes());
+            Err(hmcdk::error::from_str("failed".to_string()))
+        });
+        res.expect_err("expect error");
+        // reads only see committed state, so the writes are checked after the next transaction commits
+        call_contract(&SENDER, args![], || {
+            api::write_state("next".as_bytes(), "value".as_bytes());
+            Ok(())
+        })?;
+        assert_eq!("value", api::read_state::<String>("next".as_bytes())?);
+        api::read_state::<Vec<u8>>("other".as_bytes()).expect_err("expect error");
+        Ok(())
+    })
+    .unwrap();
+
+    // processes don't share state
+    run_process(|| {
+        init_contract_address(&CONTRACT_A)?;
+        api::read_state::<Vec<u8>>(key).expect_err("expect error");
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn large_return_values() {
+    fn func_b() -> i32 {
+        let size: u64 = api::get_arg(0).unwrap();
+        api::return_value(&value_of_size(size as usize))
+    }
+
+    for size in SIZES.iter() {
+        run_process(|| {
+            register_contract_function(CONTRACT_B, "func_b".to_string(), func_b);
+            call_contract(&SENDER, args![], || {
+                let res: Vec<u8> = api::call_contract(
+                    &CONTRACT_B,
+                    "func_b".as_bytes(),
+                    vec![&(*size as u64).to_bytes()],
+                )?;
+                assert_eq!(value_of_size(*size), res);
+                api::return_value(&res);
+                Ok(())
+            })?;
+            assert_eq!(value_of_size(*size), get_return_value::<Vec<u8>>()?);
+            Ok(())
+        })
+        .unwrap();
+    }
+}
+
+#[test]
+fn large_state_values() {
+    for size in SIZES.iter() {
+        exec_process(|| {
+            api::write_state("key".as_bytes(), &value_of_size(*size));
+            commit_state()?;
+            assert_eq!(
+                value_of_size(*size),
+                api::read_state::<Vec<u8>>("key".as_bytes())?
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+}
+
+#[test]
+fn event_ordering() {
+    fn func_b() -> i32 {
+        api::emit_event("b", "b0".as_bytes()).unwrap();
+        api::emit_event("a", "a1".as_bytes()).unwrap();
+        api::return_value(&[])
+    }
+
+    run_process(|| {
+        init_contract_address(&CONTRACT_A)?;
+        register_contract_function(CONTRACT_B, "func_b".to_string(), func_b);
+        call_contract(&SENDER, args![], || {
+            api::emit_event("a", "a0".as_bytes())?;
+            let _: Vec<u8> = api::call_contract(&CONTRACT_B, "func_b".as_bytes(), vec![])?;
+            api::emit_event("a", &value_of_size(BUF_SIZE * 2))?;
+            Ok(())
+        })?;
+
+        assert_eq!("a0".as_bytes(), &get_event("a", 0)? as &[u8]);
+        assert_eq!("a1".as_bytes(), &get_event("a", 1)? as &[u8]);
+        assert_eq!(value_of_size(BUF_SIZE * 2), get_event("a", 2)?);
+        assert_eq!("b0".as_bytes(), &get_event("b", 0)? as &[u8]);
+        get_event("a", 3).expect_err("expect error");
+        get_event("c", 0).expect_err("expect error");
+
+        let events: Vec<(String, Vec<u8>)> = get_events()?
+            .into_iter()
+            .map(|e| (e.name, e.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("a".to_string(), "a0".as_bytes().to_vec()),
+                ("b".to_string(), "b0".as_bytes().to_vec()),
+                ("a".to_string(), "a1".as_bytes().to_vec()),
+                ("a".to_string(), value_of_size(BUF_SIZE * 2)),
+            ],
+            events
+        );
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn forked_processes() {
+    fn read_b() -> i32 {
+        let v: Vec<u8> = api::read_state("key".as_bytes()).unwrap_or_default();
+        api::return_value(&v)
+    }
+
+    let forks = run_process(|| {
+        init_contract_address(&CONTRACT_A)?;
+        register_contract_function(CONTRACT_B, "read_b".to_string(), read_b);
+        call_contract(&SENDER, args![], || {
+            api::write_state("key".as_bytes(), "value_a".as_bytes());
+            Ok(())
+        })?;
+        api::write_state("key".as_bytes(), "uncommitted".as_bytes());
+        let forks = (0..4).map(|_| fork_process()).collect::<Result<Vec<_>>>()?;
+        commit_state()?;
+        assert_eq!("uncommitted", api::read_state::<String>("key".as_bytes())?);
+        Ok(forks)
+    })
+    .unwrap();
+
+    let handles: Vec<_> = forks
+        .into_iter()
+        .enumerate()
+        .map(|(i, forked)| {
+            std::thread::spawn(move || {
+                run_forked_process(forked, || {
+                    // the contract address and committed state are copied, and uncommitted writes are not
+                    assert_eq!(CONTRACT_A, api::get_contract_address()?);
+                    assert_eq!("value_a", api::read_state::<String>("key".as_bytes())?);
+                    let value = format!("value_{}", i);
+                    call_contract(&SENDER, args![], || {
+                        api::write_state("key".as_bytes(), value.as_bytes());
+                        Ok(())
+                    })?;
+                    assert_eq!(value, api::read_state::<String>("key".as_bytes())?);
+                    // registered functions are available on the new thread
+                    call_contract(&SENDER, args![], || {
+                        let v: String =
+                            api::call_contract(&CONTRACT_B, "read_b".as_bytes(), vec![])?;
+                        assert_eq!("", v);
+                        Ok(())
+                    })
+                })
+                .unwrap();
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn committed_state_view() {
+    fn write_b() -> i32 {
+        api::write_state("key".as_bytes(), &value_of_size(BUF_SIZE + 1));
+        api::return_value(&[])
+    }
+
+    run_process(|| {
+        init_contract_address(&CONTRACT_A)?;
+        register_contract_function(CONTRACT_B, "write_b".to_string(), write_b);
+        assert!(get_state()?.is_empty());
+        call_contract(&SENDER, args![], || {
+            api::write_state("key".as_bytes(), "value_a".as_bytes());
+            api::write_state(&[0u8, 0xff], &[]);
+            let _: Vec<u8> = api::call_contract(&CONTRACT_B, "write_b".as_bytes(), vec![])?;
+            // pending writes are not in the view
+            assert!(get_state()?.is_empty());
+            Ok(())
+        })?;
+
+        let state = get_state()?;
+        let entries: Vec<(Address, Vec<u8>, Vec<u8>)> = state
+            .iter()
+            .map(|(a, k, v)| (*a, k.to_vec(), v.to_vec()))
+            .collect();
+        assert_eq!(
+            vec![
+                (CONTRACT_A, vec![0u8, 0xff], vec![]),
+                (
+                    CONTRACT_A,
+                    "key".as_bytes().to_vec(),
+                    "value_a".as_bytes().to_vec()
+                ),
+                (
+                    CONTRACT_B,
+                    "key".as_bytes().to_vec(),
+                    value_of_size(BUF_SIZE + 1)
+                ),
+            ],
+            entries
+        );
+        Ok(())
+    })
+    .unwrap();
+}
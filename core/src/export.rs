@@ -0,0 +1,208 @@
+//! Contract functions collected from the whole binary, so that a contract can be deployed without registering every entry.
+//!
+//! `#[hmemu::abi]` exports every `#[contract]` function of the module with its signature,
+//! and `#[hmemu::export]` exports a single `#[contract]` function.
+//!
+//! ```ignore
+//! #[hmemu::export]
+//! #[contract]
+//! pub fn init() -> R<()> { ... }
+//!
+//! hmemu::deploy_crate_contract(CONTRACT);
+//! hmemu::invoke::<Vec<u8>>(&SENDER, &CONTRACT, "init", args![])?;
+//! ```
+use crate::abi;
+use crate::process::{
+    register_contract_function, register_contract_function_with_signature, ContractFn, Result,
+};
+use hmcdk::error;
+use hmcdk::prelude::*;
+
+/// ContractExport is the contract API exported by a `#[contract]` function, registered under its entry name.
+pub struct ContractExport {
+    pub name: &'static str,
+    pub f: ContractFn,
+    pub signature: Option<&'static abi::Function>,
+    /// module is the path of the module defining the function, e.g. `first::token`, as given by `module_path!()`.
+    pub module: &'static str,
+}
+
+inventory::collect!(ContractExport);
+
+/// deploy_crate_contract registers every exported contract function linked into the binary at the address,
+/// with its signature if it is known. It fails if two functions are exported under the same entry name,
+/// e.g. by different crates, in which case `deploy_module_contract` deploys those of one module.
+pub fn deploy_crate_contract(addr: Address) -> Result<()> {
+    register_exports(addr, inventory::iter::<ContractExport>, "in the binary")
+}
+
+/// deploy_module_contract registers the exported contract functions defined in the module or its submodules at the address,
+/// e.g. `deploy_module_contract(CONTRACT, module_path!())` or `deploy_module_contract(CONTRACT, "first::token")`.
+pub fn deploy_module_contract(addr: Address, module: &str) -> Result<()> {
+    let prefix = format!("{}::", module);
+    register_exports(
+        addr,
+        inventory::iter::<ContractExport>
+            .into_iter()
+            .filter(|e| e.module == module || e.module.starts_with(&prefix)),
+        &format!("in module {}", module),
+    )
+}
+
+// register_exports registers the exports at the address, or none of them if two share an entry name.
+// It fails if there are no exports, which are looked up in place.
+fn register_exports<'a, I: IntoIterator<Item = &'a ContractExport>>(
+    addr: Address,
+    exports: I,
+    place: &str,
+) -> Result<()> {
+    let exports: Vec<&ContractExport> = exports.into_iter().collect();
+    if exports.is_empty() {
+        return Err(error::from_str(format!(
+            "no contract functions are exported {}",
+            place
+        )));
+    }
+    for (i, export) in exports.iter().enumerate() {
+        if let Some(other) = exports[..i].iter().find(|e| e.name == export.name) {
+            return Err(error::from_str(format!(
+                "duplicate contract export `{}`: exported by {} and {}",
+                export.name, other.module, export.module
+            )));
+        }
+    }
+    for export in exports {
+        match export.signature {
+            Some(signature) => register_contract_function_with_signature(
+                addr,
+                export.name.to_string(),
+                export.f,
+                signature,
+            ),
+            None => register_contract_function(addr, export.name.to_string(), export.f),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::*;
+    use hmcdk::api;
+    use hmemu_codegen::address;
+
+    const CONTRACT: Address = address!("0x00000000000000000000000000000000000000aa");
+    static DOUBLE: abi::Function = abi::Function {
+        name: "double",
+        args: &["i64"],
+        returns: Some("i64"),
+        arity: abi::Arity::Exact,
+    };
+
+    fn __double() -> i32 {
+        let x: i64 = api::get_arg(0).unwrap();
+        api::return_value(&(2 * x).to_bytes())
+    }
+
+    fn __ping() -> i32 {
+        api::return_value("pong".as_bytes())
+    }
+
+    inventory::submit! {
+        ContractExport {
+            name: "double",
+            f: __double,
+            signature: Some(&DOUBLE),
+            module: module_path!(),
+        }
+    }
+
+    inventory::submit! {
+        ContractExport {
+            name: "ping",
+            f: __ping,
+            signature: None,
+            module: module_path!(),
+        }
+    }
+
+    #[test]
+    fn deploy_crate_contract_test() {
+        let sender = address!("0x0000000000000000000000000000000000000001");
+        run_process(|| {
+            deploy_crate_contract(CONTRACT)?;
+            assert_eq!(
+                Some(4),
+                invoke_value::<i64>(&sender, &CONTRACT, "double", crate::args![2i64])?
+            );
+            invoke::<i64>(&sender, &CONTRACT, "double", crate::args![2i32])
+                .expect_err("expect invalid argument");
+            assert_eq!(
+                Some("pong".to_string()),
+                invoke_value::<String>(&sender, &CONTRACT, "ping", crate::args![])?
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn deploy_module_contract_test() {
+        let sender = address!("0x0000000000000000000000000000000000000001");
+        run_process(|| {
+            deploy_module_contract(CONTRACT, module_path!())?;
+            assert_eq!(
+                Some(4),
+                invoke_value::<i64>(&sender, &CONTRACT, "double", crate::args![2i64])?
+            );
+            // the exports of other modules are not deployed
+            invoke::<Vec<u8>>(&sender, &CONTRACT, "scenario_mint", crate::args![1u64])
+                .expect_err("expect function not found");
+            assert_eq!(
+                "no contract functions are exported in module core::export::tests::missing",
+                deploy_module_contract(CONTRACT, "core::export::tests::missing")
+                    .unwrap_err()
+                    .to_string()
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn duplicate_export_test() {
+        let exports = [
+            ContractExport {
+                name: "ping",
+                f: __ping,
+                signature: None,
+                module: "a",
+            },
+            ContractExport {
+                name: "ping",
+                f: __ping,
+                signature: None,
+                module: "b::token",
+            },
+        ];
+        run_process(|| {
+            let err =
+                register_exports(CONTRACT, exports.iter(), "in test").expect_err("expect error");
+            assert_eq!(
+                "duplicate contract export `ping`: exported by a and b::token",
+                err.to_string()
+            );
+            // none of them is registered
+            invoke::<Vec<u8>>(
+                &address!("0x0000000000000000000000000000000000000001"),
+                &CONTRACT,
+                "ping",
+                crate::args![],
+            )
+            .expect_err("expect function not found");
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! Fuzzing contract functions with raw bytes, e.g. from a cargo-fuzz target.
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     Fuzzer::new(CONTRACT)
+//!         .register("transfer", contract_fn!(transfer))
+//!         .register("mint", contract_fn!(mint))
+//!         .with_sender()
+//!         .fuzz(data);
+//! });
+//! ```
+//!
+//! Every input runs in a fresh process after the thread-local tables are reset,
+//! so an input behaves the same no matter which inputs ran before it.
+use crate::address::to_checksum_hex;
+use crate::process::*;
+use crate::types::Args;
+use hmcdk::prelude::*;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Input is a transaction decoded from fuzzer-provided bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Input {
+    pub sender: Address,
+    pub entry: String,
+    pub args: Args,
+}
+
+/// Outcome is the result of running an input.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The contract function returned, successfully or not.
+    Returned(InvokeResult<Vec<u8>>),
+    /// The emulator failed, e.g. an invariant was violated.
+    Error(String),
+    /// The contract function panicked.
+    Panicked(String),
+}
+
+pub struct Fuzzer {
+    contract: Address,
+    entries: Vec<(String, ContractFn)>,
+    with_sender: bool,
+    setup: Option<fn() -> Result<()>>,
+}
+
+impl Fuzzer {
+    pub fn new(contract: Address) -> Self {
+        Fuzzer {
+            contract,
+            entries: Vec::new(),
+            with_sender: false,
+            setup: None,
+        }
+    }
+
+    /// register adds an entry of the contract to fuzz.
+    /// If there are several entries, the first byte of an input selects one of them.
+    pub fn register(mut self, entry: &str, f: ContractFn) -> Self {
+        self.entries.push((entry.to_string(), f));
+        self
+    }
+
+    /// with_sender takes the sender from the input instead of using the zero address.
+    pub fn with_sender(mut self) -> Self {
+        self.with_sender = true;
+        self
+    }
+
+    /// setup sets a function run in the process of every input before the transaction, e.g. to deploy initial state.
+    pub fn setup(mut self, f: fn() -> Result<()>) -> Self {
+        self.setup = Some(f);
+        self
+    }
+
+    /// decode decodes an input laid out as an optional entry selector byte, an optional 20-byte sender and the arguments.
+    /// Missing bytes are treated as zeros, so that every byte string is a valid input.
+    pub fn decode(&self, mut data: &[u8]) -> Input {
+        assert!(!self.entries.is_empty(), "no entries are registered");
+        let mut entry = &self.entries[0].0;
+        if self.entries.len() > 1 {
+            if let Some((b, rest)) = data.split_first() {
+                entry = &self.entries[*b as usize % self.entries.len()].0;
+                data = rest;
+            }
+        }
+        let mut sender: Address = Default::default();
+        if self.with_sender {
+            let n = std::cmp::min(sender.len(), data.len());
+            sender[..n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+        Input {
+            sender,
+            entry: entry.clone(),
+            args: split_args(data),
+        }
+    }
+
+    /// run runs the input decoded from data in a fresh process, and catches a panic of the contract function.
+    pub fn run(&self, data: &[u8]) -> Outcome {
+        let input = self.decode(data);
+        reset_thread_tables();
+        for (entry, f) in self.entries.iter() {
+            register_contract_function(self.contract, entry.clone(), *f);
+        }
+        let res = run_process(AssertUnwindSafe(|| {
+            init_contract_address(&self.contract)?;
+            if let Some(setup) = self.setup {
+                setup()?;
+            }
+            let res = catch_unwind(AssertUnwindSafe(|| {
+                invoke(
+                    &input.sender,
+                    &self.contract,
+                    &input.entry,
+                    input.args.clone(),
+                )
+            }));
+            Ok(match res {
+                Ok(Ok(res)) => Outcome::Returned(res),
+                Ok(Err(e)) => Outcome::Error(e.to_string()),
+                Err(e) => Outcome::Panicked(panic_message(&*e)),
+            })
+        }));
+        reset_thread_tables();
+        res.unwrap_or_else(|e| Outcome::Error(e.to_string()))
+    }
+
+    /// fuzz runs the input decoded from data, and panics if the contract function panicked or the emulator failed,
+    /// so that the fuzzer records the input as a crash.
+    pub fn fuzz(&self, data: &[u8]) {
+        match self.run(data) {
+            Outcome::Returned(_) => {}
+            Outcome::Error(e) => panic!("{}: {}", describe(&self.decode(data)), e),
+            Outcome::Panicked(e) => panic!("{} panicked: {}", describe(&self.decode(data)), e),
+        }
+    }
+}
+
+fn describe(input: &Input) -> String {
+    format!(
+        "{}(sender={}, args={:?})",
+        input.entry,
+        to_checksum_hex(&input.sender),
+        input.args
+    )
+}
+
+/// split_args splits bytes into arguments, each of which is a length byte followed by that many bytes.
+/// The last argument is truncated if the bytes run out.
+pub fn split_args(mut data: &[u8]) -> Args {
+    let mut args = Args::new();
+    while let Some((len, rest)) = data.split_first() {
+        let n = std::cmp::min(*len as usize, rest.len());
+        args.push(rest[..n].to_vec());
+        data = &rest[n..];
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmcdk::api;
+    use hmemu_codegen::address;
+
+    const CONTRACT: Address = address!("0x00000000000000000000000000000000000000aa");
+
+    fn add() -> i32 {
+        let x: i64 = api::get_arg(0).unwrap();
+        let y: i64 = api::get_arg(1).unwrap();
+        api::return_value(&(x + y).to_bytes())
+    }
+
+    fn echo() -> i32 {
+        let v: Vec<u8> = api::get_arg(0).unwrap_or_default();
+        api::return_value(&v)
+    }
+
+    // probe returns whether `late` was registered before, and registers it
+    fn probe() -> i32 {
+        let seen = api::call_contract::<Vec<u8>>(&CONTRACT, "late".as_bytes(), vec![]).is_ok();
+        register_contract_function(CONTRACT, "late".to_string(), echo);
+        api::return_value(&[seen as u8])
+    }
+
+    #[test]
+    fn split_args_test() {
+        assert_eq!(Args::new(), split_args(&[]));
+        assert_eq!(vec![vec![], vec![1u8, 2]], split_args(&[0, 2, 1, 2]));
+        assert_eq!(vec![vec![1u8]], split_args(&[3, 1]));
+    }
+
+    #[test]
+    fn decode_test() {
+        let fuzzer = Fuzzer::new(CONTRACT)
+            .register("add", add)
+            .register("echo", echo)
+            .with_sender();
+        let mut data = vec![3u8];
+        data.extend_from_slice(&[7u8; 20]);
+        data.extend_from_slice(&[1, 9]);
+        assert_eq!(
+            Input {
+                sender: [7u8; 20],
+                entry: "echo".to_string(),
+                args: vec![vec![9]],
+            },
+            fuzzer.decode(&data)
+        );
+        assert_eq!(
+            Input {
+                sender: [7u8, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                entry: "add".to_string(),
+                args: vec![],
+            },
+            fuzzer.decode(&[0, 7, 7])
+        );
+    }
+
+    #[test]
+    fn run_test() {
+        let fuzzer = Fuzzer::new(CONTRACT).register("add", add);
+        let mut data = vec![8u8];
+        data.extend_from_slice(&1i64.to_bytes());
+        data.push(8);
+        data.extend_from_slice(&2i64.to_bytes());
+        match fuzzer.run(&data) {
+            Outcome::Returned(res) => assert_eq!(Some(3i64.to_bytes()), res.value),
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+
+        // a short argument makes the contract panic while decoding it
+        for _ in 0..2 {
+            match fuzzer.run(&[1, 1]) {
+                Outcome::Panicked(_) => {}
+                outcome => panic!("unexpected outcome: {:?}", outcome),
+            }
+        }
+        // the process of the panicked input was destroyed
+        run_process(|| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn repeat_test() {
+        // the first input registers a contract function, and the second one calls it if it is left behind
+        let fuzzer = Fuzzer::new(CONTRACT).register("probe", probe);
+        let first = fuzzer.run(&[]);
+        match first {
+            Outcome::Returned(ref res) => assert_eq!(Some(vec![0u8]), res.value),
+            ref outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+        assert_eq!(first, fuzzer.run(&[]));
+    }
+}
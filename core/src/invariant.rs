@@ -0,0 +1,171 @@
+use crate::process::{get_state, get_write_set, Result};
+use crate::state::{StateChange, StateView};
+use hmcdk::error;
+use std::cell::RefCell;
+use std::sync::Arc;
+
+type InvariantFn = Arc<dyn Fn(&StateView) -> Result<()> + Send + Sync>;
+
+// Invariants are the invariants of the process attached to the current thread,
+// and the number of transactions committed by it.
+#[derive(Clone, Default)]
+pub(crate) struct Invariants {
+    checks: Vec<(String, InvariantFn)>,
+    commits: usize,
+}
+
+thread_local!(static INVARIANTS: RefCell<Invariants> = RefCell::new(Invariants::default()));
+
+/// add_invariant registers an invariant on the current process.
+/// It is checked against the committed state after every `commit_state`,
+/// and a violation makes the commit fail with the index of the transaction and the state diff it made.
+/// The commit isn't rolled back, so the state made by the violating transaction stays committed
+/// and the process shouldn't be used for further steps.
+///
+/// ```ignore
+/// hmemu::add_invariant("supply", |state| {
+///     let total: u64 = state.read(&TOKEN, b"supply")?.unwrap_or(0);
+///     let sum: u64 = state
+///         .contract(&TOKEN)
+///         .filter(|(k, _)| k.starts_with(b"balance/"))
+///         .map(|(_, v)| u64::from_bytes(v.to_vec()).unwrap())
+///         .sum();
+///     if sum != total {
+///         return Err(hmcdk::error::from_str(format!("sum {} != supply {}", sum, total)));
+///     }
+///     Ok(())
+/// });
+/// ```
+pub fn add_invariant<F>(name: &str, f: F)
+where
+    F: Fn(&StateView) -> Result<()> + Send + Sync + 'static,
+{
+    INVARIANTS.with(|t| t.borrow_mut().checks.push((name.to_string(), Arc::new(f))));
+}
+
+pub(crate) fn current() -> Invariants {
+    INVARIANTS.with(|t| t.borrow().clone())
+}
+
+pub(crate) fn replace(invariants: Invariants) -> Invariants {
+    INVARIANTS.with(|t| t.replace(invariants))
+}
+
+// state_before_commit returns the keys the commit writes with their values before it, only if there are invariants to check.
+pub(crate) fn state_before_commit() -> Result<Option<Vec<StateChange>>> {
+    if INVARIANTS.with(|t| t.borrow().checks.is_empty()) {
+        Ok(None)
+    } else {
+        get_write_set().map(Some)
+    }
+}
+
+// after_commit counts the committed transaction, and checks the invariants against the state it made.
+pub(crate) fn after_commit(before: Option<Vec<StateChange>>) -> Result<()> {
+    let invariants = INVARIANTS.with(|t| {
+        let mut t = t.borrow_mut();
+        t.commits += 1;
+        t.clone()
+    });
+    let before = match before {
+        Some(before) => before,
+        None => return Ok(()),
+    };
+    let after = get_state()?;
+    for (name, check) in invariants.checks.iter() {
+        if let Err(e) = check(&after) {
+            let diff: Vec<String> = before
+                .into_iter()
+                .map(|c| StateChange {
+                    after: after.get(&c.address, &c.key).map(|v| v.to_vec()),
+                    ..c
+                })
+                .filter(|c| c.before != c.after)
+                .map(|c| format!("  {}", c))
+                .collect();
+            return Err(error::from_str(format!(
+                "invariant \"{}\" violated by transaction {}: {}\nstate diff:\n{}",
+                name,
+                invariants.commits - 1,
+                e,
+                diff.join("\n")
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::to_checksum_hex;
+    use crate::process::*;
+    use hmcdk::api;
+    use hmcdk::prelude::*;
+    use hmemu_codegen::address;
+
+    const TOKEN: Address = address!("0x00000000000000000000000000000000000000aa");
+
+    fn mint() -> i32 {
+        let amount: u64 = api::get_arg(0).unwrap();
+        let supply: u64 = api::read_state("supply".as_bytes()).unwrap_or(0);
+        let balance: u64 = api::read_state("balance".as_bytes()).unwrap_or(0);
+        api::write_state("supply".as_bytes(), &(supply + amount).to_bytes());
+        api::write_state("balance".as_bytes(), &(balance + amount).to_bytes());
+        api::return_value(&[])
+    }
+
+    // buggy_mint forgets to increase the supply.
+    fn buggy_mint() -> i32 {
+        let amount: u64 = api::get_arg(0).unwrap();
+        let balance: u64 = api::read_state("balance".as_bytes()).unwrap_or(0);
+        api::write_state("balance".as_bytes(), &(balance + amount).to_bytes());
+        api::return_value(&[])
+    }
+
+    fn supply_invariant(state: &StateView) -> Result<()> {
+        let supply: u64 = state.read(&TOKEN, b"supply")?.unwrap_or(0);
+        let balance: u64 = state.read(&TOKEN, b"balance")?.unwrap_or(0);
+        if supply != balance {
+            return Err(error::from_str(format!(
+                "balance {} != supply {}",
+                balance, supply
+            )));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invariant_test() {
+        let sender = address!("0x0000000000000000000000000000000000000001");
+        run_process(|| {
+            register_contract_function(TOKEN, "mint".to_string(), mint);
+            register_contract_function(TOKEN, "buggy_mint".to_string(), buggy_mint);
+            add_invariant("supply", supply_invariant);
+
+            invoke::<Vec<u8>>(&sender, &TOKEN, "mint", crate::args![10u64])?;
+            invoke::<Vec<u8>>(&sender, &TOKEN, "mint", crate::args![5u64])?;
+            let err = invoke::<Vec<u8>>(&sender, &TOKEN, "buggy_mint", crate::args![1u64])
+                .expect_err("expect error");
+            assert_eq!(
+                format!(
+                    "invariant \"supply\" violated by transaction 2: balance 16 != supply 15\nstate diff:\n  {} \"balance\": 0x000000000000000f -> 0x0000000000000010",
+                    to_checksum_hex(&TOKEN)
+                ),
+                err.to_string()
+            );
+            // the violating transaction stays committed
+            assert_eq!(Some(16), get_state()?.read::<u64>(&TOKEN, b"balance")?);
+            Ok(())
+        })
+        .unwrap();
+
+        // invariants belong to the process
+        run_process(|| {
+            register_contract_function(TOKEN, "buggy_mint".to_string(), buggy_mint);
+            invoke::<Vec<u8>>(&sender, &TOKEN, "buggy_mint", crate::args![1u64])?;
+            Ok(())
+        })
+        .unwrap();
+    }
+}
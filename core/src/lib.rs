@@ -1,3 +1,29 @@
+pub mod abi;
+pub mod accounts;
+pub mod address;
+#[cfg(test)]
+mod conformance;
+pub mod export;
+pub mod fuzz;
+pub mod invariant;
+#[cfg(feature = "backend-native")]
+mod native;
+#[cfg(feature = "plugin")]
+pub mod plugin;
 pub mod process;
+#[cfg(feature = "proptest")]
+pub mod proptest;
+#[cfg(feature = "scenario")]
+pub mod scenario;
+pub mod state;
+#[cfg(feature = "proptest")]
+pub mod state_machine;
+pub mod trace;
 pub mod types;
+pub use export::{deploy_crate_contract, deploy_module_contract, ContractExport};
+pub use invariant::add_invariant;
+#[doc(hidden)]
+pub use inventory;
 pub use process::*;
+#[cfg(feature = "scenario")]
+pub use scenario::run_scenario;
@@ -0,0 +1,1072 @@
+//! A pure-Rust implementation of the host functions exported by the Go runtime in `runtime/`.
+//! It is enabled by the `backend-native` feature and needs neither a Go toolchain nor `libhm`.
+use crate::accounts::pubkey_to_address;
+use crate::address::to_checksum_hex;
+use hmcdk::prelude::*;
+use lazy_static::lazy_static;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+
+lazy_static! {
+    static ref PROCESS_MANAGER: Mutex<ProcessManager> = Mutex::new(ProcessManager::default());
+}
+
+thread_local!(static ATTACHED_PID: Cell<i32> = Cell::new(-1));
+// LAST_ERROR is why the last failed host call of the thread failed, which __get_last_error returns.
+thread_local!(static LAST_ERROR: RefCell<Option<String>> = RefCell::new(None));
+
+// ProcessManager manages processes, each of which is attached to at most one thread.
+// Host calls resolve their process from the calling thread, so processes on different threads run concurrently.
+//
+// Slots of destroyed processes are reused. A pid carries the generation of its slot,
+// so a stale pid of a destroyed process never refers to the process reusing the slot.
+#[derive(Default)]
+struct ProcessManager {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+    entry: Option<ProcessEntry>,
+}
+
+struct ProcessEntry {
+    process: Arc<Mutex<Process>>,
+    thread: Option<ThreadId>,
+}
+
+const PID_INDEX_BITS: u32 = 16;
+const PID_INDEX_MASK: i32 = (1 << PID_INDEX_BITS) - 1;
+// the generation wraps before it reaches the sign bit, so that a pid is never negative
+const PID_GENERATION_MASK: u32 = (1 << (31 - PID_INDEX_BITS)) - 1;
+
+fn make_pid(index: usize, generation: u32) -> i32 {
+    ((generation << PID_INDEX_BITS) as i32) | index as i32
+}
+
+fn split_pid(pid: i32) -> (usize, u32) {
+    (
+        (pid & PID_INDEX_MASK) as usize,
+        (pid >> PID_INDEX_BITS) as u32,
+    )
+}
+
+impl ProcessManager {
+    fn create_process(&mut self) -> Result<i32, String> {
+        let tid = thread::current().id();
+        let pid = self.insert(Process::default(), Some(tid))?;
+        ATTACHED_PID.with(|p| p.set(pid));
+        Ok(pid)
+    }
+
+    // fork_process copies the process attached to the calling thread into a new process which no thread is attached to.
+    fn fork_process(&mut self) -> Result<i32, String> {
+        let ps = self.current_process()?;
+        let forked = lock(&ps).fork();
+        self.insert(forked, None)
+    }
+
+    fn insert(&mut self, process: Process, thread: Option<ThreadId>) -> Result<i32, String> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None if self.slots.len() <= PID_INDEX_MASK as usize => {
+                self.slots.push(Slot::default());
+                self.slots.len() - 1
+            }
+            None => return Err("too many processes".to_string()),
+        };
+        let slot = &mut self.slots[index];
+        slot.entry = Some(ProcessEntry {
+            process: Arc::new(Mutex::new(process)),
+            thread,
+        });
+        Ok(make_pid(index, slot.generation))
+    }
+
+    fn entry_mut(&mut self, pid: i32) -> Result<&mut ProcessEntry, String> {
+        let (index, generation) = split_pid(pid);
+        match self.slots.get_mut(index) {
+            Some(Slot {
+                generation: g,
+                entry: Some(entry),
+            }) if pid >= 0 && *g == generation => Ok(entry),
+            Some(_) => Err(format!("stale pid {}", pid)),
+            None => Err(format!("not found pid {}", pid)),
+        }
+    }
+
+    fn attach(&mut self, pid: i32) -> Result<(), String> {
+        let entry = self.entry_mut(pid)?;
+        let tid = thread::current().id();
+        match entry.thread {
+            Some(t) if t != tid => Err(format!("pid {} is attached to another thread", pid)),
+            _ => {
+                entry.thread = Some(tid);
+                ATTACHED_PID.with(|p| p.set(pid));
+                Ok(())
+            }
+        }
+    }
+
+    fn detach(&mut self) {
+        let pid = ATTACHED_PID.with(|p| p.replace(-1));
+        if let Ok(entry) = self.entry_mut(pid) {
+            entry.thread = None;
+        }
+    }
+
+    fn destroy_process(&mut self) -> Result<(), String> {
+        let pid = ATTACHED_PID.with(|p| p.replace(-1));
+        self.entry_mut(pid)?;
+        let (index, _) = split_pid(pid);
+        let slot = &mut self.slots[index];
+        slot.entry = None;
+        slot.generation = (slot.generation + 1) & PID_GENERATION_MASK;
+        self.free.push(index);
+        Ok(())
+    }
+
+    fn current_process(&mut self) -> Result<Arc<Mutex<Process>>, String> {
+        let pid = ATTACHED_PID.with(|p| p.get());
+        if pid < 0 {
+            return Err("no process is attached".to_string());
+        }
+        Ok(self.entry_mut(pid)?.process.clone())
+    }
+
+    fn live_process_count(&self) -> usize {
+        self.slots.iter().filter(|s| s.entry.is_some()).count()
+    }
+}
+
+// lock ignores poisoning, as a panicking contract must not make every following test fail.
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    match m.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+fn manager() -> MutexGuard<'static, ProcessManager> {
+    lock(&PROCESS_MANAGER)
+}
+
+// fail records err as the last error of the calling thread, and returns the status of a failed host call.
+fn fail(err: String) -> i32 {
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(err));
+    -1
+}
+
+// with_process runs f with the process attached to the calling thread, or returns -1 if there is none.
+fn with_process<F: FnOnce(&mut Process) -> i32>(f: F) -> i32 {
+    // the manager is only locked while looking up the process
+    let ps = manager().current_process();
+    match ps {
+        Ok(ps) => f(&mut lock(&ps)),
+        Err(e) => fail(e),
+    }
+}
+
+/// VersionedStore is the committed key-value state of a process.
+/// Each value remembers the version of the commit which wrote it.
+#[derive(Default, Clone)]
+struct VersionedStore {
+    version: u64,
+    values: BTreeMap<Vec<u8>, (Vec<u8>, u64)>,
+}
+
+impl VersionedStore {
+    fn get(&self, addr: &Address, key: &[u8]) -> Option<&[u8]> {
+        self.values
+            .get(&prefixed_key(addr, key))
+            .map(|(v, _)| v.as_slice())
+    }
+
+    fn commit(&mut self, sets: &[RWSet]) {
+        self.version += 1;
+        for set in sets.iter() {
+            for (k, v) in set.writes.iter() {
+                self.values
+                    .insert(prefixed_key(&set.address, k), (v.clone(), self.version));
+            }
+        }
+    }
+}
+
+fn prefixed_key(addr: &Address, key: &[u8]) -> Vec<u8> {
+    let mut k = addr.to_vec();
+    k.extend_from_slice(key);
+    k
+}
+
+// StateDB buffers the writes of a single contract until they are committed.
+// Like hypermint's VersionedDB, reads only see committed state.
+#[derive(Default, Clone)]
+struct StateDB {
+    writes: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+struct RWSet {
+    address: Address,
+    writes: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+// Frame is the caller's context saved while a nested contract call is running.
+struct Frame {
+    initialized: bool,
+    contract_address: Address,
+    sender: Address,
+    args: Vec<Vec<u8>>,
+    res: Vec<u8>,
+    db: StateDB,
+    // sets is the number of the write sets of nested calls the caller had made
+    sets: usize,
+}
+
+// Trace records the host calls of a process while tracing is enabled.
+struct Trace {
+    start: Instant,
+    events: Vec<TraceRecord>,
+}
+
+struct TraceRecord {
+    timestamp: u64,
+    depth: u32,
+    contract: Address,
+    name: Vec<u8>,
+    args: Vec<Vec<u8>>,
+    result: i32,
+    output: Vec<u8>,
+}
+
+impl Trace {
+    fn new() -> Self {
+        Trace {
+            start: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    // serialize encodes each record as length-prefixed name, contract address, timestamp, depth, result, output,
+    // the number of arguments and the arguments, where the numbers are big-endian.
+    fn serialize(&self) -> Vec<u8> {
+        let mut bs = Vec::new();
+        for r in self.events.iter() {
+            let fields: [&[u8]; 7] = [
+                &r.name,
+                &r.contract,
+                &r.timestamp.to_be_bytes(),
+                &r.depth.to_be_bytes(),
+                &r.result.to_be_bytes(),
+                &r.output,
+                &(r.args.len() as u32).to_be_bytes(),
+            ];
+            for b in fields
+                .iter()
+                .copied()
+                .chain(r.args.iter().map(|a| a.as_slice()))
+            {
+                bs.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                bs.extend_from_slice(b);
+            }
+        }
+        bs
+    }
+}
+
+#[derive(Default)]
+struct Process {
+    initialized: bool,
+    trace: Option<Trace>,
+    store: VersionedStore,
+    db: StateDB,
+
+    contract_address: Address,
+    sender: Address,
+    args: Vec<Vec<u8>>,
+    res: Vec<u8>,
+
+    state_stack: Vec<Frame>,
+    sets: Vec<RWSet>,
+    events: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Process {
+    // init_contract_address starts a transaction, so a return value of a previous one must not leak into it.
+    fn init_contract_address(&mut self, addr: Address) {
+        self.contract_address = addr;
+        self.db = StateDB::default();
+        self.res = Vec::new();
+    }
+
+    // fork returns a process which shares nothing with self but a copy of the committed state and the contract address.
+    fn fork(&self) -> Process {
+        Process {
+            trace: self.trace.as_ref().map(|_| Trace::new()),
+            store: self.store.clone(),
+            contract_address: self.contract_address,
+            ..Default::default()
+        }
+    }
+
+    fn push_state(&mut self, next_contract: Address) {
+        self.state_stack.push(Frame {
+            initialized: self.initialized,
+            contract_address: self.contract_address,
+            sender: self.sender,
+            args: std::mem::take(&mut self.args),
+            res: std::mem::take(&mut self.res),
+            db: std::mem::take(&mut self.db),
+            sets: self.sets.len(),
+        });
+        self.initialized = false;
+        self.sender = self.contract_address;
+        self.contract_address = next_contract;
+    }
+
+    fn pop_state(&mut self) {
+        let top = self.state_stack.pop().expect("stack is empty");
+        let db = std::mem::replace(&mut self.db, top.db);
+        self.sets.push(RWSet {
+            address: self.contract_address,
+            writes: db.writes,
+        });
+        self.initialized = top.initialized;
+        self.sender = top.sender;
+        self.args = top.args;
+        self.res = top.res;
+        self.contract_address = top.contract_address;
+    }
+
+    // discard_state drops the pending writes of the current call and of the nested calls it made.
+    fn discard_state(&mut self) {
+        self.db = StateDB::default();
+        let n = self.state_stack.last().map_or(0, |f| f.sets);
+        self.sets.truncate(n);
+    }
+
+    fn commit_state(&mut self) {
+        let mut sets = std::mem::take(&mut self.sets);
+        let db = std::mem::take(&mut self.db);
+        sets.push(RWSet {
+            address: self.contract_address,
+            writes: db.writes,
+        });
+        for set in sets.iter().filter(|s| !s.writes.is_empty()) {
+            let mut writes = vec![set.address.to_vec()];
+            for (k, v) in set.writes.iter() {
+                writes.push(k.clone());
+                writes.push(v.clone());
+            }
+            self.trace_at(set.address, "commit_state", writes, 0, &[]);
+        }
+        self.store.commit(&sets);
+    }
+
+    // trace records a host call of the current contract if tracing is enabled.
+    fn trace(&mut self, name: &str, args: Vec<Vec<u8>>, result: i32, output: &[u8]) {
+        self.trace_at(self.contract_address, name, args, result, output)
+    }
+
+    fn trace_at(
+        &mut self,
+        contract: Address,
+        name: &str,
+        args: Vec<Vec<u8>>,
+        result: i32,
+        output: &[u8],
+    ) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.events.push(TraceRecord {
+                timestamp: trace.start.elapsed().as_nanos() as u64,
+                depth: self.state_stack.len() as u32,
+                contract,
+                name: name.as_bytes().to_vec(),
+                args,
+                result,
+                output: output.to_vec(),
+            });
+        }
+    }
+
+    fn read_state(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.store
+            .get(&self.contract_address, key)
+            .map(|v| v.to_vec())
+    }
+
+    fn write_state(&mut self, key: &[u8], value: &[u8]) {
+        self.db.writes.insert(key.to_vec(), value.to_vec());
+    }
+
+    // serialize_events encodes each event as a length-prefixed name followed by a length-prefixed value.
+    fn serialize_events(&self) -> Vec<u8> {
+        let mut bs = Vec::new();
+        for (name, value) in self.events.iter() {
+            for b in &[name, value] {
+                bs.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                bs.extend_from_slice(b);
+            }
+        }
+        bs
+    }
+
+    // serialize_state encodes the committed state as triples of length-prefixed contract address, key and value,
+    // ordered by address and key.
+    fn serialize_state(&self) -> Vec<u8> {
+        let mut bs = Vec::new();
+        for (k, (v, _)) in self.store.values.iter() {
+            let (addr, key) = k.split_at(20);
+            for b in &[addr, key, v] {
+                bs.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                bs.extend_from_slice(b);
+            }
+        }
+        bs
+    }
+
+    // serialize_write_set encodes the keys of the pending writes as length-prefixed contract address and key,
+    // followed by the value committed for the key, whose length is 0xffffffff if the key isn't committed.
+    fn serialize_write_set(&self) -> Vec<u8> {
+        let mut keys = BTreeSet::new();
+        for set in self.sets.iter() {
+            keys.extend(set.writes.keys().map(|k| (set.address, k.clone())));
+        }
+        keys.extend(
+            self.db
+                .writes
+                .keys()
+                .map(|k| (self.contract_address, k.clone())),
+        );
+        let mut bs = Vec::new();
+        for (addr, key) in keys.iter() {
+            for b in &[&addr[..], key] {
+                bs.extend_from_slice(&(b.len() as u32).to_be_bytes());
+                bs.extend_from_slice(b);
+            }
+            match self.store.get(addr, key) {
+                Some(v) => {
+                    bs.extend_from_slice(&(v.len() as u32).to_be_bytes());
+                    bs.extend_from_slice(v);
+                }
+                None => bs.extend_from_slice(&u32::MAX.to_be_bytes()),
+            }
+        }
+        bs
+    }
+}
+
+unsafe fn read_bytes(ptr: *const u8, len: usize) -> Vec<u8> {
+    if len == 0 {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(ptr, len).to_vec()
+}
+
+// written returns the bytes a host call wrote into the buffer, for tracing.
+unsafe fn written(ptr: *const u8, ret: i32) -> Vec<u8> {
+    if ret <= 0 {
+        return Vec::new();
+    }
+    read_bytes(ptr, ret as usize)
+}
+
+// trace_current records a host call on the process attached to the calling thread, if any.
+fn trace_current(name: &str, args: Vec<Vec<u8>>, result: i32, output: &[u8]) {
+    if let Ok(ps) = manager().current_process() {
+        lock(&ps).trace(name, args, result, output);
+    }
+}
+
+unsafe fn read_address(ptr: *const u8, len: usize) -> Address {
+    let mut addr: Address = Default::default();
+    let b = read_bytes(ptr, len);
+    let n = std::cmp::min(addr.len(), b.len());
+    addr[..n].copy_from_slice(&b[..n]);
+    addr
+}
+
+// write_buf copies value[offset..] into the buffer as far as it fits, and returns the copied size.
+unsafe fn write_buf(ptr: *mut u8, len: usize, offset: usize, value: &[u8]) -> i32 {
+    if offset >= value.len() {
+        return 0;
+    }
+    let size = std::cmp::min(len, value.len() - offset);
+    std::ptr::copy_nonoverlapping(value[offset..].as_ptr(), ptr, size);
+    size as i32
+}
+
+// write_exact copies value into the buffer, or returns -1 if it doesn't fit.
+unsafe fn write_exact(ptr: *mut u8, len: usize, value: &[u8]) -> i32 {
+    if value.len() > len {
+        return -1;
+    }
+    write_buf(ptr, len, 0, value)
+}
+
+// deserialize_args decodes arguments serialized by hmcdk as a sequence of 4-byte big-endian length prefixed values.
+fn deserialize_args(mut bs: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut args = Vec::new();
+    while !bs.is_empty() {
+        if bs.len() < 4 {
+            return Err("invalid args: unexpected eof".to_string());
+        }
+        let mut size = [0u8; 4];
+        size.copy_from_slice(&bs[..4]);
+        let size = u32::from_be_bytes(size) as usize;
+        if bs.len() < 4 + size {
+            return Err("invalid args: unexpected eof".to_string());
+        }
+        args.push(bs[4..4 + size].to_vec());
+        bs = &bs[4 + size..];
+    }
+    Ok(args)
+}
+
+// recover_pubkey recovers an uncompressed public key, accepting v both as a recovery id and as 27 plus the recovery id.
+fn recover_pubkey(h: &[u8], v: &[u8], r: &[u8], s: &[u8]) -> Option<[u8; 65]> {
+    if h.len() != 32 || v.len() != 1 || r.len() != 32 || s.len() != 32 {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(h);
+    let mut sig = [0u8; 64];
+    sig[..32].copy_from_slice(r);
+    sig[32..].copy_from_slice(s);
+    let recid = if v[0] >= 27 { v[0] - 27 } else { v[0] };
+    let recid = secp256k1::RecoveryId::parse(recid).ok()?;
+    let pubkey = secp256k1::recover(
+        &secp256k1::Message::parse(&hash),
+        &secp256k1::Signature::parse(&sig),
+        &recid,
+    )
+    .ok()?;
+    Some(pubkey.serialize())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __init_process() -> i32 {
+    manager().create_process().unwrap_or_else(fail)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __destroy_process() -> i32 {
+    match manager().destroy_process() {
+        Ok(()) => 0,
+        Err(e) => fail(e),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __fork_process() -> i32 {
+    manager().fork_process().unwrap_or_else(fail)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __live_process_count() -> i32 {
+    manager().live_process_count() as i32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __init_contract_address(ptr: *const u8, len: usize) -> i32 {
+    with_process(|ps| {
+        if ps.initialized {
+            return fail("the process is already initialized".to_string());
+        }
+        ps.init_contract_address(read_address(ptr, len));
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __init_sender(ptr: *const u8, len: usize) -> i32 {
+    with_process(|ps| {
+        if ps.initialized {
+            return fail("the process is already initialized".to_string());
+        }
+        ps.sender = read_address(ptr, len);
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __init_push_arg(ptr: *const u8, len: usize) -> i32 {
+    with_process(|ps| {
+        if ps.initialized {
+            return fail("the process is already initialized".to_string());
+        }
+        ps.args.push(read_bytes(ptr, len));
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __init_args(ptr: *const u8, len: usize) -> i32 {
+    with_process(|ps| {
+        if ps.initialized {
+            return fail("the process is already initialized".to_string());
+        }
+        match deserialize_args(&read_bytes(ptr, len)) {
+            Ok(args) => {
+                ps.args = args;
+                0
+            }
+            Err(e) => fail(e),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __init_done() -> i32 {
+    with_process(|ps| {
+        if ps.initialized {
+            return fail("the process is already initialized".to_string());
+        }
+        ps.initialized = true;
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __clear() -> i32 {
+    with_process(|ps| {
+        ps.sender = Default::default();
+        ps.args = Vec::new();
+        ps.initialized = false;
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __commit_state() -> i32 {
+    with_process(|ps| {
+        ps.commit_state();
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __discard_state() -> i32 {
+    with_process(|ps| {
+        ps.discard_state();
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_return_value(offset: usize, ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| write_buf(ptr, len, offset, &ps.res))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_return_value_len() -> i32 {
+    with_process(|ps| ps.res.len() as i32)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_event(
+    name_ptr: *const u8,
+    name_len: usize,
+    idx: usize,
+    offset: usize,
+    buf_ptr: *mut u8,
+    buf_len: usize,
+) -> i32 {
+    let name = read_bytes(name_ptr, name_len);
+    with_process(
+        |ps| match ps.events.iter().filter(|(n, _)| *n == name).nth(idx) {
+            Some((_, v)) => write_buf(buf_ptr, buf_len, offset, v),
+            None => -1,
+        },
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_event_len(name_ptr: *const u8, name_len: usize, idx: usize) -> i32 {
+    let name = read_bytes(name_ptr, name_len);
+    with_process(
+        |ps| match ps.events.iter().filter(|(n, _)| *n == name).nth(idx) {
+            Some((_, v)) => v.len() as i32,
+            None => -1,
+        },
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_events(offset: usize, ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| write_buf(ptr, len, offset, &ps.serialize_events()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_events_len() -> i32 {
+    with_process(|ps| ps.serialize_events().len() as i32)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_write_set(offset: usize, ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| write_buf(ptr, len, offset, &ps.serialize_write_set()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_write_set_len() -> i32 {
+    with_process(|ps| ps.serialize_write_set().len() as i32)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_state(offset: usize, ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| write_buf(ptr, len, offset, &ps.serialize_state()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_state_len() -> i32 {
+    with_process(|ps| ps.serialize_state().len() as i32)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_sender(ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| {
+        let ret = write_exact(ptr, len, &ps.sender);
+        ps.trace("get_sender", vec![], ret, &written(ptr, ret));
+        ret
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_contract_address(ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| {
+        let ret = write_exact(ptr, len, &ps.contract_address);
+        ps.trace("get_contract_address", vec![], ret, &written(ptr, ret));
+        ret
+    })
+}
+
+// __get_init_contract_address reads the contract address like __get_contract_address,
+// but isn't traced since only the harness calls it.
+#[no_mangle]
+pub unsafe extern "C" fn __get_init_contract_address(ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| write_exact(ptr, len, &ps.contract_address))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_arg(idx: usize, offset: usize, ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| {
+        let ret = match ps.args.get(idx) {
+            Some(arg) => write_buf(ptr, len, offset, arg),
+            None => -1,
+        };
+        let idx = (idx as u32).to_be_bytes().to_vec();
+        ps.trace("get_arg", vec![idx], ret, &written(ptr, ret));
+        ret
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __set_response(ptr: *const u8, len: usize) -> i32 {
+    with_process(|ps| {
+        ps.res = read_bytes(ptr, len);
+        ps.trace("set_response", vec![ps.res.clone()], 0, &[]);
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __log(ptr: *const u8, len: usize) -> i32 {
+    let msg = read_bytes(ptr, len);
+    with_process(|ps| {
+        println!(
+            "[{}] {}",
+            to_checksum_hex(&ps.contract_address),
+            String::from_utf8_lossy(&msg)
+        );
+        ps.trace("log", vec![msg], 0, &[]);
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __read_state(
+    key_ptr: *const u8,
+    key_len: usize,
+    offset: usize,
+    buf_ptr: *mut u8,
+    buf_len: usize,
+) -> i32 {
+    let key = read_bytes(key_ptr, key_len);
+    with_process(|ps| {
+        let ret = match ps.read_state(&key) {
+            Some(v) => write_buf(buf_ptr, buf_len, offset, &v),
+            None => -1,
+        };
+        ps.trace("read_state", vec![key], ret, &written(buf_ptr, ret));
+        ret
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __write_state(
+    key_ptr: *const u8,
+    key_len: usize,
+    val_ptr: *const u8,
+    val_len: usize,
+) -> i32 {
+    let key = read_bytes(key_ptr, key_len);
+    let val = read_bytes(val_ptr, val_len);
+    with_process(|ps| {
+        ps.write_state(&key, &val);
+        ps.trace("write_state", vec![key, val], 0, &[]);
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __attach_process(pid: i32) -> i32 {
+    match manager().attach(pid) {
+        Ok(()) => 0,
+        Err(e) => fail(e),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __detach_process() -> i32 {
+    manager().detach();
+    0
+}
+
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn __ecrecover(
+    h: *const u8,
+    h_len: usize,
+    v: *const u8,
+    v_len: usize,
+    r: *const u8,
+    r_len: usize,
+    s: *const u8,
+    s_len: usize,
+    buf: *mut u8,
+    buf_len: usize,
+) -> i32 {
+    let args = vec![
+        read_bytes(h, h_len),
+        read_bytes(v, v_len),
+        read_bytes(r, r_len),
+        read_bytes(s, s_len),
+    ];
+    let ret = match recover_pubkey(&args[0], &args[1], &args[2], &args[3]) {
+        Some(pubkey) => write_exact(buf, buf_len, &pubkey),
+        None => -1,
+    };
+    trace_current("ecrecover", args, ret, &written(buf, ret));
+    ret
+}
+
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn __ecrecover_address(
+    h: *const u8,
+    h_len: usize,
+    v: *const u8,
+    v_len: usize,
+    r: *const u8,
+    r_len: usize,
+    s: *const u8,
+    s_len: usize,
+    buf: *mut u8,
+    buf_len: usize,
+) -> i32 {
+    let args = vec![
+        read_bytes(h, h_len),
+        read_bytes(v, v_len),
+        read_bytes(r, r_len),
+        read_bytes(s, s_len),
+    ];
+    let ret = match recover_pubkey(&args[0], &args[1], &args[2], &args[3]) {
+        Some(pubkey) => write_exact(buf, buf_len, &pubkey_to_address(&pubkey)),
+        None => -1,
+    };
+    trace_current("ecrecover_address", args, ret, &written(buf, ret));
+    ret
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __emit_event(
+    ev_ptr: *const u8,
+    ev_len: usize,
+    data_ptr: *const u8,
+    data_len: usize,
+) -> i32 {
+    let name = read_bytes(ev_ptr, ev_len);
+    let data = read_bytes(data_ptr, data_len);
+    with_process(|ps| {
+        ps.trace("emit_event", vec![name.clone(), data.clone()], 0, &[]);
+        ps.events.push((name, data));
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __push_contract_state(addr_ptr: *const u8, addr_len: usize) -> i32 {
+    let addr = read_address(addr_ptr, addr_len);
+    with_process(|ps| {
+        ps.push_state(addr);
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __pop_contract_state() -> i32 {
+    with_process(|ps| {
+        ps.pop_state();
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __set_trace(flag: u8) -> i32 {
+    with_process(|ps| {
+        match flag {
+            0 => ps.trace = None,
+            1 => {
+                if ps.trace.is_none() {
+                    ps.trace = Some(Trace::new())
+                }
+            }
+            _ => return fail(format!("unknown trace flag {}", flag)),
+        }
+        0
+    })
+}
+
+// __trace_event records an event which only the harness knows about, e.g. the entry and status of a contract call.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn __trace_event(
+    name_ptr: *const u8,
+    name_len: usize,
+    args_ptr: *const u8,
+    args_len: usize,
+    result: i32,
+    output_ptr: *const u8,
+    output_len: usize,
+) -> i32 {
+    let name = String::from_utf8_lossy(&read_bytes(name_ptr, name_len)).into_owned();
+    let output = read_bytes(output_ptr, output_len);
+    match deserialize_args(&read_bytes(args_ptr, args_len)) {
+        Ok(args) => with_process(|ps| {
+            ps.trace(&name, args, result, &output);
+            0
+        }),
+        Err(e) => fail(e),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_trace(offset: usize, ptr: *mut u8, len: usize) -> i32 {
+    with_process(|ps| match ps.trace.as_ref() {
+        Some(trace) => write_buf(ptr, len, offset, &trace.serialize()),
+        None => 0,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_trace_len() -> i32 {
+    with_process(|ps| match ps.trace.as_ref() {
+        Some(trace) => trace.serialize().len() as i32,
+        None => 0,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __clear_trace() -> i32 {
+    with_process(|ps| {
+        if let Some(trace) = ps.trace.as_mut() {
+            trace.events.clear();
+        }
+        0
+    })
+}
+
+// __get_last_error copies the last error of the calling thread into the buffer, and clears it.
+#[no_mangle]
+pub unsafe extern "C" fn __get_last_error(ptr: *mut u8, len: usize) -> i32 {
+    LAST_ERROR.with(|e| match e.borrow_mut().take() {
+        Some(err) => write_exact(ptr, len, err.as_bytes()),
+        None => -1,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __get_last_error_len() -> i32 {
+    LAST_ERROR.with(|e| match e.borrow().as_ref() {
+        Some(err) => err.len() as i32,
+        None => -1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_manager_test() {
+        let mut pm = ProcessManager::default();
+        let pid1 = pm.create_process().unwrap();
+        let pid2 = pm.create_process().unwrap();
+        assert_eq!(2, pm.live_process_count());
+
+        pm.attach(pid1).unwrap();
+        pm.destroy_process().unwrap();
+        assert_eq!(1, pm.live_process_count());
+        pm.attach(pid1).expect_err("expect error");
+
+        // the slot is reused with a new generation
+        let pid3 = pm.create_process().unwrap();
+        assert_ne!(pid1, pid3);
+        assert_eq!(split_pid(pid1).0, split_pid(pid3).0);
+        assert_eq!(2, pm.slots.len());
+        pm.attach(pid1).expect_err("expect error");
+        pm.attach(pid3).unwrap();
+        pm.attach(pid2).unwrap();
+        pm.destroy_process().unwrap();
+        pm.attach(pid3).unwrap();
+        pm.destroy_process().unwrap();
+        assert_eq!(0, pm.live_process_count());
+    }
+
+    #[test]
+    fn attach_test() {
+        let mut pm = ProcessManager::default();
+        let pid = pm.create_process().unwrap();
+        let pm = Arc::new(Mutex::new(pm));
+        let pm2 = pm.clone();
+        thread::spawn(move || {
+            lock(&pm2).attach(pid).expect_err("expect error");
+        })
+        .join()
+        .unwrap();
+
+        lock(&pm).detach();
+        let pm2 = pm.clone();
+        thread::spawn(move || {
+            let mut pm = lock(&pm2);
+            pm.attach(pid).unwrap();
+            pm.detach();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn pid_test() {
+        for &(index, generation) in &[(0, 0), (1, 0), (0, 1), (0xffff, PID_GENERATION_MASK)] {
+            let pid = make_pid(index, generation);
+            assert!(pid >= 0);
+            assert_eq!((index, generation), split_pid(pid));
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Contracts loaded at runtime from shared libraries.
+use crate::process::{ContractFn, Result};
+use hmcdk::error;
+use libloading::Library;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Plugin is a contract crate built as a shared library, e.g. with `crate-type = ["cdylib"]`.
+/// Its host function imports are resolved against the emulator linked into the binary,
+/// and its `#[contract]` functions are looked up by their exported contract API `__<entry>`.
+pub struct Plugin {
+    pub path: PathBuf,
+    lib: Library,
+}
+
+impl Plugin {
+    /// load loads a contract plugin.
+    /// A wasm module is rejected, since the emulator runs contracts natively and has no wasm engine.
+    pub fn load(path: &Path) -> Result<Self> {
+        if is_wasm(path) {
+            return Err(error::from_str(format!(
+                "{}: wasm modules are not supported since hmemu has no wasm engine; build the contract as a cdylib and load the shared library instead",
+                path.display()
+            )));
+        }
+        let lib = unsafe { Library::new(path) }
+            .map_err(|e| error::from_str(format!("{}: {}", path.display(), e)))?;
+        Ok(Plugin {
+            path: path.to_path_buf(),
+            lib,
+        })
+    }
+
+    /// function returns the contract API exported by the `#[contract]` function of the entry.
+    pub fn function(&self, entry: &str) -> Option<ContractFn> {
+        let name = format!("__{}", entry);
+        // the plugin is never unloaded, so the function pointer stays valid
+        unsafe { self.lib.get::<ContractFn>(name.as_bytes()).ok().map(|f| *f) }
+    }
+}
+
+fn is_wasm(path: &Path) -> bool {
+    if matches!(path.extension(), Some(ext) if ext == "wasm") {
+        return true;
+    }
+    let mut magic = [0u8; 4];
+    match File::open(path).and_then(|mut f| f.read_exact(&mut magic)) {
+        Ok(()) => &magic == b"\0asm",
+        Err(_) => false,
+    }
+}
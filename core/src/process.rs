@@ -1,25 +1,47 @@
+use crate::abi;
+use crate::address::to_checksum_hex;
+use crate::invariant::{self, Invariants};
+use crate::state::{StateChange, StateView};
+use crate::trace::TraceEvent;
 use crate::types::Args;
 use hmcdk::error;
 use hmcdk::prelude::*;
+use std::any::Any;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe, UnwindSafe};
 
+#[cfg(feature = "backend-native")]
+use crate::native::{
+    __attach_process, __clear, __clear_trace, __commit_state, __destroy_process, __detach_process,
+    __discard_state, __fork_process, __get_event, __get_event_len, __get_events, __get_events_len,
+    __get_init_contract_address, __get_last_error, __get_last_error_len, __get_return_value,
+    __get_return_value_len, __get_state, __get_state_len, __get_trace, __get_trace_len,
+    __get_write_set, __get_write_set_len, __init_args, __init_contract_address, __init_done,
+    __init_process, __init_push_arg, __init_sender, __live_process_count, __pop_contract_state,
+    __push_contract_state, __set_trace, __trace_event,
+};
+
+#[cfg(not(feature = "backend-native"))]
 #[link(name = "hm", kind = "dylib")]
 extern "C" {
     fn __init_process() -> i32;
     fn __destroy_process() -> i32;
+    fn __fork_process() -> i32;
+    fn __live_process_count() -> i32;
     fn __init_contract_address(value_ptr: *const u8, value_len: usize) -> i32;
     fn __init_sender(value_ptr: *const u8, value_len: usize) -> i32;
     fn __init_push_arg(value_ptr: *const u8, value_len: usize) -> i32;
     fn __init_args(values_ptr: *const u8, values_len: usize) -> i32;
     fn __init_done() -> i32;
     fn __clear() -> i32;
+    fn __get_init_contract_address(value_buf_ptr: *mut u8, value_buf_len: usize) -> i32;
 
     fn __commit_state() -> i32;
+    fn __discard_state() -> i32;
 
-    fn __get_mutex(pid: i32) -> i32;
-    fn __release_mutex() -> i32;
+    fn __attach_process(pid: i32) -> i32;
+    fn __detach_process() -> i32;
 
     fn __get_return_value(offset: usize, value_buf_ptr: *mut u8, value_buf_len: usize) -> i32;
     fn __get_event(
@@ -30,31 +52,77 @@ extern "C" {
         value_buf_ptr: *mut u8,
         value_buf_len: usize,
     ) -> i32;
+    fn __get_events(offset: usize, value_buf_ptr: *mut u8, value_buf_len: usize) -> i32;
+    fn __get_state(offset: usize, value_buf_ptr: *mut u8, value_buf_len: usize) -> i32;
+    fn __get_write_set(offset: usize, value_buf_ptr: *mut u8, value_buf_len: usize) -> i32;
+    fn __get_last_error(value_buf_ptr: *mut u8, value_buf_len: usize) -> i32;
+
+    // the lengths of the values above, or -1 if a value doesn't exist
+    fn __get_return_value_len() -> i32;
+    fn __get_event_len(name: *const u8, name_len: usize, idx: usize) -> i32;
+    fn __get_events_len() -> i32;
+    fn __get_state_len() -> i32;
+    fn __get_write_set_len() -> i32;
+    fn __get_trace_len() -> i32;
+    fn __get_last_error_len() -> i32;
 
     fn __push_contract_state(addr_ptr: *const u8, addr_len: usize) -> i32;
     fn __pop_contract_state() -> i32;
-    fn __set_debug(flag: u8) -> i32;
+
+    fn __set_trace(flag: u8) -> i32;
+    fn __trace_event(
+        name_ptr: *const u8,
+        name_len: usize,
+        args_ptr: *const u8,
+        args_len: usize,
+        result: i32,
+        output_ptr: *const u8,
+        output_len: usize,
+    ) -> i32;
+    fn __get_trace(offset: usize, value_buf_ptr: *mut u8, value_buf_len: usize) -> i32;
+    fn __clear_trace() -> i32;
 }
 
 pub type Result<T> = std::result::Result<T, error::Error>;
 
 thread_local!(static PID: RefCell<i32> = RefCell::new(-1));
 
-// get_mutex gets mutex
-fn get_mutex() -> Result<()> {
+/// last_error returns why the last failed host call of the current thread failed, if the backend recorded it,
+/// and clears it.
+pub fn last_error() -> Option<String> {
+    read_value(
+        "__get_last_error",
+        "__get_last_error: not found",
+        || unsafe { __get_last_error_len() },
+        |ptr, len| unsafe { __get_last_error(ptr, len) },
+    )
+    .ok()
+    .map(|bs| String::from_utf8_lossy(&bs).into_owned())
+}
+
+// host_error is the error of a host call which returned ret, with the reason the backend recorded for it.
+fn host_error(name: &str, ret: i32) -> error::Error {
+    match last_error() {
+        Some(reason) => error::from_str(format!("{}: error({}): {}", name, ret, reason)),
+        None => error::from_str(format!("{}: error({})", name, ret)),
+    }
+}
+
+// attach_process makes host calls from the current thread operate on the process of pid.
+fn attach_process(pid: i32) -> Result<()> {
     unsafe {
-        match __get_mutex(get_pid()) {
-            ret if ret < 0 => Err(error::from_str(format!("__get_mutex: error({})", ret))),
+        match __attach_process(pid) {
+            ret if ret < 0 => Err(host_error("__attach_process", ret)),
             _ => Ok(()),
         }
     }
 }
 
-// release_mutex release mutex
-fn release_mutex() -> Result<()> {
+// detach_process releases the process attached to the current thread.
+fn detach_process() -> Result<()> {
     unsafe {
-        match __release_mutex() {
-            ret if ret < 0 => Err(error::from_str(format!("__release_mutex: error({})", ret))),
+        match __detach_process() {
+            ret if ret < 0 => Err(host_error("__detach_process", ret)),
             _ => Ok(()),
         }
     }
@@ -72,11 +140,12 @@ pub fn init_process() -> Result<i32> {
     }
     unsafe {
         match __init_process() {
-            ret if ret < 0 => Err(error::from_str(format!("__init_process: error({})", ret))),
+            ret if ret < 0 => Err(host_error("__init_process", ret)),
             pid => {
                 PID.with(|p| {
                     *p.borrow_mut() = pid;
                 });
+                invariant::replace(Invariants::default());
                 Ok(pid)
             }
         }
@@ -86,19 +155,25 @@ pub fn init_process() -> Result<i32> {
 pub fn init_contract_address(addr: &[u8]) -> Result<()> {
     unsafe {
         match __init_contract_address(addr.as_ptr(), addr.len()) {
-            ret if ret < 0 => Err(error::from_str(format!(
-                "__init_contract_address: error({})",
-                ret
-            ))),
+            ret if ret < 0 => Err(host_error("__init_contract_address", ret)),
             _ => Ok(()),
         }
     }
 }
 
+// contract_address returns the contract address of the current process.
+fn contract_address() -> Result<Address> {
+    let mut addr = Address::default();
+    match unsafe { __get_init_contract_address(addr.as_mut_ptr(), addr.len()) } {
+        ret if ret < 0 => Err(host_error("__get_init_contract_address", ret)),
+        _ => Ok(addr),
+    }
+}
+
 pub fn init_sender(addr: &[u8]) -> Result<()> {
     unsafe {
         match __init_sender(addr.as_ptr(), addr.len()) {
-            ret if ret < 0 => Err(error::from_str(format!("__init_sender: error({})", ret))),
+            ret if ret < 0 => Err(host_error("__init_sender", ret)),
             _ => Ok(()),
         }
     }
@@ -107,7 +182,7 @@ pub fn init_sender(addr: &[u8]) -> Result<()> {
 pub fn init_push_arg(b: &[u8]) -> Result<()> {
     unsafe {
         match __init_push_arg(b.as_ptr(), b.len()) {
-            ret if ret < 0 => Err(error::from_str(format!("__init_push_arg: error({})", ret))),
+            ret if ret < 0 => Err(host_error("__init_push_arg", ret)),
             _ => Ok(()),
         }
     }
@@ -116,100 +191,479 @@ pub fn init_push_arg(b: &[u8]) -> Result<()> {
 pub fn init_done() -> Result<()> {
     unsafe {
         match __init_done() {
-            ret if ret < 0 => Err(error::from_str(format!("__init_done: error({})", ret))),
+            ret if ret < 0 => Err(host_error("__init_done", ret)),
             _ => Ok(()),
         }
     }
 }
 
+// clear ends the current transaction, and drops the values its nested calls returned.
 pub fn clear() -> Result<()> {
+    drop_values();
     unsafe {
         match __clear() {
-            ret if ret < 0 => Err(error::from_str(format!("__clear: error({})", ret))),
+            ret if ret < 0 => Err(host_error("__clear", ret)),
             _ => Ok(()),
         }
     }
 }
 
+// commit_state commits the pending writes, and checks the invariants of the process against the new state.
 pub fn commit_state() -> Result<()> {
+    let before = invariant::state_before_commit()?;
     unsafe {
         match __commit_state() {
-            ret if ret < 0 => Err(error::from_str(format!("__commit_state: error({})", ret))),
+            ret if ret < 0 => Err(host_error("__commit_state", ret)),
+            _ => invariant::after_commit(before),
+        }
+    }
+}
+
+// discard_state drops the pending writes of the current call and of the nested calls it made,
+// e.g. when the call failed.
+pub fn discard_state() -> Result<()> {
+    unsafe {
+        match __discard_state() {
+            ret if ret < 0 => Err(host_error("__discard_state", ret)),
             _ => Ok(()),
         }
     }
 }
 
-const BUF_SIZE: usize = 128;
+// push_contract_state saves the context of the current call and starts a call of the contract at addr,
+// whose writes are kept apart from those of the caller until pop_contract_state.
+pub fn push_contract_state(addr: &Address) -> Result<()> {
+    unsafe {
+        match __push_contract_state(addr.as_ptr(), addr.len()) {
+            ret if ret < 0 => Err(host_error("__push_contract_state", ret)),
+            _ => Ok(()),
+        }
+    }
+}
 
-pub fn get_return_value<T: FromBytes>() -> Result<T> {
-    let mut buf = [0u8; BUF_SIZE];
-    let mut offset = 0;
-    let mut val: Vec<u8> = Vec::new();
-    loop {
-        match unsafe { __get_return_value(offset, buf.as_mut_ptr(), buf.len()) } {
-            -1 => {
-                return Err(error::from_str(
-                    "__get_return_value: key not found".to_string(),
-                ))
-            }
-            0 => break,
-            n => {
-                val.extend_from_slice(&buf[0..n as usize]);
-                if n < BUF_SIZE as i32 {
-                    break;
-                }
-                offset += n as usize;
-            }
+// pop_contract_state ends the call started by push_contract_state and restores the context of the caller.
+// The writes of the call stay pending until the next commit.
+pub fn pop_contract_state() -> Result<()> {
+    unsafe {
+        match __pop_contract_state() {
+            ret if ret < 0 => Err(host_error("__pop_contract_state", ret)),
+            _ => Ok(()),
         }
     }
+}
+
+// BUF_SIZE is the size of the buffer hmcdk reads values from the host with,
+// so values around it are the edge cases of chunked reads.
+#[cfg(test)]
+pub(crate) const BUF_SIZE: usize = 128;
+
+// read_value reads a value from the host in one shot: len reports its size, or -1 if it doesn't exist,
+// and read copies it into a buffer of that size.
+fn read_value<L, R>(context: &str, not_found: &str, len: L, read: R) -> Result<Vec<u8>>
+where
+    L: FnOnce() -> i32,
+    R: FnOnce(*mut u8, usize) -> i32,
+{
+    let n = len();
+    if n < 0 {
+        return Err(error::from_str(not_found.to_string()));
+    }
+    let mut buf = vec![0u8; n as usize];
+    match read(buf.as_mut_ptr(), buf.len()) {
+        ret if ret == n => Ok(buf),
+        ret => Err(error::from_str(format!(
+            "{}: read {} of {} bytes",
+            context, ret, n
+        ))),
+    }
+}
+
+pub fn get_return_value<T: FromBytes>() -> Result<T> {
+    let val = read_value(
+        "__get_return_value",
+        "__get_return_value: key not found",
+        || unsafe { __get_return_value_len() },
+        |ptr, len| unsafe { __get_return_value(0, ptr, len) },
+    )?;
     Ok(T::from_bytes(val)?)
 }
 
 pub fn get_event(name: &str, idx: usize) -> Result<Vec<u8>> {
-    let mut buf = [0u8; BUF_SIZE];
-    let mut offset = 0;
-    let mut val: Vec<u8> = Vec::new();
-    loop {
-        match unsafe {
-            __get_event(
-                name.as_ptr(),
-                name.len(),
-                idx,
-                offset,
-                buf.as_mut_ptr(),
-                buf.len(),
-            )
-        } {
-            -1 => return Err(error::from_str("get_event: event not found".to_string())),
-            0 => break,
-            n => {
-                val.extend_from_slice(&buf[0..n as usize]);
-                if n < BUF_SIZE as i32 {
-                    break;
-                }
-                offset += n as usize;
-            }
+    read_value(
+        "__get_event",
+        "get_event: event not found",
+        || unsafe { __get_event_len(name.as_ptr(), name.len(), idx) },
+        |ptr, len| unsafe { __get_event(name.as_ptr(), name.len(), idx, 0, ptr, len) },
+    )
+}
+
+/// Event is an event emitted by a contract.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+// get_events returns all events emitted in the current process in emission order.
+pub fn get_events() -> Result<Vec<Event>> {
+    let val = read_value(
+        "__get_events",
+        "__get_events: error(-1)",
+        || unsafe { __get_events_len() },
+        |ptr, len| unsafe { __get_events(0, ptr, len) },
+    )?;
+    decode_events(&val)
+}
+
+// decode_events decodes events serialized as pairs of length-prefixed name and value.
+fn decode_events(mut bs: &[u8]) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+    while !bs.is_empty() {
+        let name = String::from_utf8(next_length_prefixed(&mut bs, "decode_events")?.to_vec())
+            .map_err(|e| error::from_str(format!("decode_events: {}", e)))?;
+        let value = next_length_prefixed(&mut bs, "decode_events")?.to_vec();
+        events.push(Event { name, value });
+    }
+    Ok(events)
+}
+
+// get_state returns the committed state of every contract in the current process.
+pub fn get_state() -> Result<StateView> {
+    let val = read_value(
+        "__get_state",
+        "__get_state: error(-1)",
+        || unsafe { __get_state_len() },
+        |ptr, len| unsafe { __get_state(0, ptr, len) },
+    )?;
+    decode_state(&val)
+}
+
+// decode_state decodes entries serialized as triples of length-prefixed contract address, key and value.
+fn decode_state(mut bs: &[u8]) -> Result<StateView> {
+    let mut state = StateView::default();
+    while !bs.is_empty() {
+        let addr = next_length_prefixed(&mut bs, "decode_state")?;
+        if addr.len() != 20 {
+            return Err(error::from_str(format!(
+                "decode_state: invalid address length {}",
+                addr.len()
+            )));
+        }
+        let mut address: Address = Default::default();
+        address.copy_from_slice(addr);
+        let key = next_length_prefixed(&mut bs, "decode_state")?.to_vec();
+        let value = next_length_prefixed(&mut bs, "decode_state")?.to_vec();
+        state.insert(address, key, value);
+    }
+    Ok(state)
+}
+
+// get_write_set returns the keys the pending writes of the current process overwrite when they are committed,
+// with the values committed for them as `before`.
+pub(crate) fn get_write_set() -> Result<Vec<StateChange>> {
+    let val = read_value(
+        "__get_write_set",
+        "__get_write_set: error(-1)",
+        || unsafe { __get_write_set_len() },
+        |ptr, len| unsafe { __get_write_set(0, ptr, len) },
+    )?;
+    decode_write_set(&val)
+}
+
+// decode_write_set decodes keys serialized as length-prefixed contract address and key followed by the committed value,
+// whose length is 0xffffffff if the key isn't committed.
+fn decode_write_set(mut bs: &[u8]) -> Result<Vec<StateChange>> {
+    let mut changes = Vec::new();
+    while !bs.is_empty() {
+        let addr = next_length_prefixed(&mut bs, "decode_write_set")?;
+        if addr.len() != 20 {
+            return Err(error::from_str(format!(
+                "decode_write_set: invalid address length {}",
+                addr.len()
+            )));
         }
+        let mut address: Address = Default::default();
+        address.copy_from_slice(addr);
+        let key = next_length_prefixed(&mut bs, "decode_write_set")?.to_vec();
+        let before = if bs.starts_with(&u32::MAX.to_be_bytes()) {
+            bs = &bs[4..];
+            None
+        } else {
+            Some(next_length_prefixed(&mut bs, "decode_write_set")?.to_vec())
+        };
+        changes.push(StateChange {
+            address,
+            key,
+            before,
+            after: None,
+        });
+    }
+    Ok(changes)
+}
+
+// next_length_prefixed returns the next value prefixed by its 4-byte big-endian length, and advances bs past it.
+fn next_length_prefixed<'a>(bs: &mut &'a [u8], context: &str) -> Result<&'a [u8]> {
+    if bs.len() < 4 {
+        return Err(error::from_str(format!("{}: unexpected eof", context)));
+    }
+    let mut size = [0u8; 4];
+    size.copy_from_slice(&bs[..4]);
+    let size = u32::from_be_bytes(size) as usize;
+    if bs.len() < 4 + size {
+        return Err(error::from_str(format!("{}: unexpected eof", context)));
+    }
+    let v = &bs[4..4 + size];
+    *bs = &bs[4 + size..];
+    Ok(v)
+}
+
+/// set_trace enables or disables recording the host calls of the current process.
+pub fn set_trace(b: bool) -> Result<()> {
+    match unsafe { __set_trace(if b { 1 } else { 0 }) } {
+        0 => Ok(()),
+        ret => Err(host_error("__set_trace", ret)),
+    }
+}
+
+/// take_trace returns the events recorded since tracing was enabled or the trace was last taken, and clears them.
+pub fn take_trace() -> Result<Vec<TraceEvent>> {
+    let val = read_value(
+        "__get_trace",
+        "__get_trace: error(-1)",
+        || unsafe { __get_trace_len() },
+        |ptr, len| unsafe { __get_trace(0, ptr, len) },
+    )?;
+    match unsafe { __clear_trace() } {
+        0 => {}
+        ret => return Err(host_error("__clear_trace", ret)),
     }
-    Ok(val)
+    decode_trace(&val)
+}
+
+// decode_trace decodes events serialized as length-prefixed name, contract address, timestamp, depth, result, output,
+// the number of arguments and the arguments.
+fn decode_trace(mut bs: &[u8]) -> Result<Vec<TraceEvent>> {
+    let context = "decode_trace";
+    let mut events = Vec::new();
+    while !bs.is_empty() {
+        let name = String::from_utf8(next_length_prefixed(&mut bs, context)?.to_vec())
+            .map_err(|e| error::from_str(format!("{}: {}", context, e)))?;
+        let mut contract: Address = Default::default();
+        contract.copy_from_slice(next_fixed(&mut bs, context, 20)?);
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(next_fixed(&mut bs, context, 8)?);
+        let mut depth = [0u8; 4];
+        depth.copy_from_slice(next_fixed(&mut bs, context, 4)?);
+        let mut result = [0u8; 4];
+        result.copy_from_slice(next_fixed(&mut bs, context, 4)?);
+        let output = next_length_prefixed(&mut bs, context)?.to_vec();
+        let mut n_args = [0u8; 4];
+        n_args.copy_from_slice(next_fixed(&mut bs, context, 4)?);
+        let mut args = Vec::new();
+        for _ in 0..u32::from_be_bytes(n_args) {
+            args.push(next_length_prefixed(&mut bs, context)?.to_vec());
+        }
+        events.push(TraceEvent {
+            timestamp: u64::from_be_bytes(timestamp),
+            depth: u32::from_be_bytes(depth),
+            contract,
+            name,
+            args,
+            result: i32::from_be_bytes(result),
+            output,
+        });
+    }
+    Ok(events)
+}
+
+// next_fixed returns the next length-prefixed value, which must be size bytes long.
+fn next_fixed<'a>(bs: &mut &'a [u8], context: &str, size: usize) -> Result<&'a [u8]> {
+    let v = next_length_prefixed(bs, context)?;
+    if v.len() != size {
+        return Err(error::from_str(format!(
+            "{}: invalid length {}, expected {}",
+            context,
+            v.len(),
+            size
+        )));
+    }
+    Ok(v)
+}
+
+// trace_event records an event of the harness, e.g. a contract call, in the trace of the current process.
+// The backend ignores it unless tracing is enabled.
+fn trace_event(name: &str, args: &[&[u8]], result: i32, output: &[u8]) -> Result<()> {
+    let mut bs = Vec::new();
+    for arg in args.iter() {
+        bs.extend_from_slice(&(arg.len() as u32).to_be_bytes());
+        bs.extend_from_slice(arg);
+    }
+    let ret = unsafe {
+        __trace_event(
+            name.as_ptr(),
+            name.len(),
+            bs.as_ptr(),
+            bs.len(),
+            result,
+            output.as_ptr(),
+            output.len(),
+        )
+    };
+    if ret != 0 {
+        return Err(host_error("__trace_event", ret));
+    }
+    Ok(())
 }
 
 pub fn destroy_process() -> Result<()> {
+    drop_values();
+    clear_call_error();
     unsafe {
         match __destroy_process() {
-            ret if ret < 0 => Err(error::from_str(format!(
-                "__destroy_process: error({})",
-                ret
-            ))),
-            _ => PID.with(|p| {
-                *p.borrow_mut() = -1;
+            ret if ret < 0 => Err(host_error("__destroy_process", ret)),
+            _ => {
+                PID.with(|p| {
+                    *p.borrow_mut() = -1;
+                });
+                invariant::replace(Invariants::default());
                 Ok(())
+            }
+        }
+    }
+}
+
+// ForkedProcess is a process forked from the current one, which no thread is attached to yet.
+// It can be sent to another thread and run there by run_forked_process. It is destroyed if it is dropped without being run.
+pub struct ForkedProcess {
+    pid: i32,
+    functions: HashMap<(Address, String), ContractEntry>,
+    invariants: Invariants,
+}
+
+impl ForkedProcess {
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+}
+
+impl Drop for ForkedProcess {
+    fn drop(&mut self) {
+        // the pid is taken by run_forked_process
+        if self.pid < 0 {
+            return;
+        }
+        let current = get_pid();
+        if attach_process(self.pid).is_ok() {
+            unsafe {
+                __destroy_process();
+            }
+        }
+        if current >= 0 {
+            let _ = attach_process(current);
+        }
+    }
+}
+
+// fork_process creates a new process with copies of the committed state, the contract address, the registered contract functions and the invariants of the current process.
+// Uncommitted writes, the sender and the arguments are not copied.
+pub fn fork_process() -> Result<ForkedProcess> {
+    unsafe {
+        match __fork_process() {
+            ret if ret < 0 => Err(host_error("__fork_process", ret)),
+            pid => Ok(ForkedProcess {
+                pid,
+                functions: FUNC_TABLE.with(|t| t.borrow().clone()),
+                invariants: invariant::current(),
             }),
         }
     }
 }
 
+// run_forked_process runs f with the forked process attached to the current thread, and destroys the process after that.
+pub fn run_forked_process<T, F: FnOnce() -> Result<T>>(forked: ForkedProcess, f: F) -> Result<T>
+where
+    F: UnwindSafe,
+{
+    if get_pid() >= 0 {
+        // the fork is destroyed when it is dropped
+        return Err(error::from_str("process already exists".to_string()));
+    }
+    let mut forked = forked;
+    let pid = std::mem::replace(&mut forked.pid, -1);
+    let _run = ForkedRun {
+        functions: FUNC_TABLE.with(|t| t.borrow().clone()),
+    };
+    FUNC_TABLE.with(|t| t.borrow_mut().extend(forked.functions.drain()));
+    invariant::replace(std::mem::take(&mut forked.invariants));
+    PID.with(|p| {
+        *p.borrow_mut() = pid;
+    });
+    exec_function(|| {
+        let res = f();
+        destroy_process()?;
+        res
+    })
+}
+
+// ForkedRun restores the thread after run_forked_process, even if f panics:
+// it destroys the process unless it is destroyed already, and drops the contract functions and the invariants of the fork.
+struct ForkedRun {
+    // functions are the contract functions the thread had before the run
+    functions: HashMap<(Address, String), ContractEntry>,
+}
+
+impl Drop for ForkedRun {
+    fn drop(&mut self) {
+        let pid = get_pid();
+        if pid >= 0 && attach_process(pid).is_ok() {
+            let _ = destroy_process();
+        }
+        // the pid is left over when the process couldn't be attached
+        PID.with(|p| {
+            *p.borrow_mut() = -1;
+        });
+        invariant::replace(Invariants::default());
+        let functions = std::mem::take(&mut self.functions);
+        FUNC_TABLE.with(|t| *t.borrow_mut() = functions);
+    }
+}
+
+// suspend_process runs f with the process of the current thread detached, so that f can run another process on this thread.
+#[cfg(feature = "proptest")]
+pub(crate) fn suspend_process<T, F: FnOnce() -> T>(f: F) -> Result<T> {
+    let pid = get_pid();
+    detach_process()?;
+    PID.with(|p| {
+        *p.borrow_mut() = -1;
+    });
+    let invariants = invariant::replace(Invariants::default());
+    let result = catch_unwind(AssertUnwindSafe(f));
+    invariant::replace(invariants);
+    PID.with(|p| {
+        *p.borrow_mut() = pid;
+    });
+    attach_process(pid)?;
+    match result {
+        Ok(v) => Ok(v),
+        Err(err) => resume_unwind(err),
+    }
+}
+
+pub(crate) fn panic_message(e: &(dyn Any + Send)) -> String {
+    if let Some(s) = e.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = e.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// live_process_count returns the number of processes which are not destroyed yet.
+pub fn live_process_count() -> usize {
+    unsafe { __live_process_count() as usize }
+}
+
 pub fn exec_process<T, F: FnOnce() -> Result<T>>(cb: F) -> Result<T>
 where
     F: UnwindSafe,
@@ -243,11 +697,15 @@ where
     run_process(|| call_contract(sender, args, cb))
 }
 
+// exec_function executes f with the process of the current thread attached.
+// Processes are independent of each other, so functions on different threads run concurrently.
 pub fn exec_function<T, F: FnOnce() -> Result<T>>(f: F) -> Result<T>
 where
     F: UnwindSafe,
 {
-    get_mutex()?;
+    if get_pid() >= 0 {
+        attach_process(get_pid())?;
+    }
     let mut res: Result<T> = Err(error::from_str(""));
     let result = {
         let mut resref = AssertUnwindSafe(&mut res);
@@ -255,7 +713,7 @@ where
             **resref = f();
         })
     };
-    release_mutex()?;
+    detach_process()?;
     if let Err(err) = result {
         resume_unwind(err)
     } else {
@@ -279,11 +737,14 @@ where
     }
 }
 
+// call_contract runs cb as a transaction of sender with args, and commits state if it succeeds.
+// The writes of a failed transaction are discarded.
 pub fn call_contract<T, F: FnOnce() -> Result<T>>(
     sender: &Address,
     args: Vec<Vec<u8>>,
     cb: F,
 ) -> Result<T> {
+    let tx = Transaction::begin(None);
     init_sender(sender)?;
     for arg in args.into_iter() {
         init_push_arg(&arg)?;
@@ -295,30 +756,85 @@ pub fn call_contract<T, F: FnOnce() -> Result<T>>(
         }
         e => e,
     };
-    clear()?;
+    tx.end()?;
     res
 }
 
+// OPEN_TRANSACTIONS counts the transactions which call_contract and invoke are running in each process.
+thread_local!(static OPEN_TRANSACTIONS: RefCell<HashMap<i32, usize>> = RefCell::new(HashMap::new()));
+
+// Transaction ends a transaction of call_contract or invoke when it is dropped, even if a contract function panics.
+// The writes a failed transaction left pending, including those of its nested calls, are discarded,
+// so that the next commit doesn't commit them.
+struct Transaction {
+    pid: i32,
+    // restore is the contract address to point the process at after the transaction
+    restore: Option<Address>,
+    ended: bool,
+}
+
+impl Transaction {
+    fn begin(restore: Option<Address>) -> Transaction {
+        let pid = get_pid();
+        clear_call_error();
+        OPEN_TRANSACTIONS.with(|t| *t.borrow_mut().entry(pid).or_insert(0) += 1);
+        Transaction {
+            pid,
+            restore,
+            ended: false,
+        }
+    }
+
+    fn end(mut self) -> Result<()> {
+        self.ended = true;
+        self.close()
+    }
+
+    fn close(&self) -> Result<()> {
+        OPEN_TRANSACTIONS.with(|t| {
+            let mut t = t.borrow_mut();
+            if let Some(n) = t.get_mut(&self.pid) {
+                *n -= 1;
+                if *n == 0 {
+                    t.remove(&self.pid);
+                }
+            }
+        });
+        discard_state()?;
+        clear()?;
+        match self.restore {
+            Some(ref addr) => init_contract_address(addr),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.close();
+        }
+    }
+}
+
+fn in_transaction() -> bool {
+    OPEN_TRANSACTIONS.with(|t| t.borrow().contains_key(&get_pid()))
+}
+
 #[no_mangle]
 pub fn __read(id: usize, offset: usize, value_buf_ptr: *mut u8, value_buf_len: usize) -> i32 {
     VALUE_TABLE.with(|t| {
-        let v = &t.borrow()[id];
-        let mut size: usize = 0;
-        let mut ptr = value_buf_ptr;
-        let mut count = 0;
-        for b in v {
-            if offset > count {
-                count += 1;
-                continue;
-            }
-            unsafe {
-                *ptr = *b;
-                ptr = ptr.wrapping_add(1);
-            }
-            size += 1;
-            if size == value_buf_len {
-                break;
-            }
+        let t = t.borrow();
+        let v = match t.get(&get_pid()).and_then(|values| values.get(id)) {
+            Some(v) => v,
+            None => return -1,
+        };
+        if offset >= v.len() {
+            return 0;
+        }
+        let size = std::cmp::min(value_buf_len, v.len() - offset);
+        unsafe {
+            std::ptr::copy_nonoverlapping(v[offset..].as_ptr(), value_buf_ptr, size);
         }
         size as i32
     })
@@ -326,17 +842,57 @@ pub fn __read(id: usize, offset: usize, value_buf_ptr: *mut u8, value_buf_len: u
 
 pub type ContractFn = fn() -> i32;
 
-thread_local!(static VALUE_TABLE: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new()));
-thread_local!(static FUNC_TABLE: RefCell<HashMap<(Address, String), ContractFn >> = RefCell::new(HashMap::new()));
+// MAX_VALUES is the maximum number of values a transaction can keep in the value table.
+const MAX_VALUES: usize = 4096;
 
-pub fn __write(v: Vec<u8>) -> usize {
+// VALUE_TABLE keeps the return values of nested contract calls until the caller reads them.
+// Each process has its own values, which are dropped at the end of every transaction.
+thread_local!(static VALUE_TABLE: RefCell<HashMap<i32, Vec<Vec<u8>>>> = RefCell::new(HashMap::new()));
+thread_local!(static FUNC_TABLE: RefCell<HashMap<(Address, String), ContractEntry>> = RefCell::new(HashMap::new()));
+
+// ContractEntry is a registered contract function, with the signature its arguments are validated against if it is known.
+#[derive(Clone, Copy)]
+struct ContractEntry {
+    f: ContractFn,
+    signature: Option<&'static abi::Function>,
+}
+
+// reset_thread_tables clears the values, the call errors and the contract functions kept by the current thread.
+pub(crate) fn reset_thread_tables() {
+    VALUE_TABLE.with(|t| t.borrow_mut().clear());
+    CALL_ERRORS.with(|t| t.borrow_mut().clear());
+    FUNC_TABLE.with(|t| t.borrow_mut().clear());
+}
+
+#[cfg(test)]
+pub(crate) fn value_table_len() -> usize {
+    VALUE_TABLE.with(|t| t.borrow().values().map(|values| values.len()).sum())
+}
+
+// __write keeps v in the value table of the current process, and returns its id, or -1 if the table is full.
+pub fn __write(v: Vec<u8>) -> i32 {
     VALUE_TABLE.with(|t| {
-        let mut vv = t.borrow_mut();
-        vv.push(v);
-        vv.len() - 1
+        let mut t = t.borrow_mut();
+        let values = t.entry(get_pid()).or_insert_with(Vec::new);
+        if values.len() >= MAX_VALUES {
+            return -1;
+        }
+        values.push(v);
+        (values.len() - 1) as i32
     })
 }
 
+// values_full returns whether the value table of the current process can't keep another value.
+fn values_full() -> bool {
+    VALUE_TABLE
+        .with(|t| matches!(t.borrow().get(&get_pid()), Some(values) if values.len() >= MAX_VALUES))
+}
+
+// drop_values drops the values kept by the current process.
+fn drop_values() {
+    VALUE_TABLE.with(|t| t.borrow_mut().remove(&get_pid()));
+}
+
 #[no_mangle]
 pub fn __call_contract(
     addr_ptr: *const u8,
@@ -363,64 +919,312 @@ pub fn __call_contract(
         }
         e_ptr = e_ptr.wrapping_add(1);
     }
-    let entry_name = String::from_utf8(entry).unwrap();
+    let mut call: Vec<&[u8]> = vec![&addr, &entry];
+    let entry_name = match std::str::from_utf8(&entry) {
+        Ok(name) => name,
+        Err(e) => return call_error(&call, &format!("__call_contract: invalid entry: {}", e)),
+    };
+    let contract = match lookup_contract_function(&addr, entry_name) {
+        Some(contract) => contract,
+        None => {
+            return call_error(
+                &call,
+                &format!(
+                    "function not found: address={} entry={}",
+                    to_checksum_hex(&addr),
+                    entry_name
+                ),
+            )
+        }
+    };
+    let mut rest = if args_size == 0 {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(args, args_size) }
+    };
+    while !rest.is_empty() {
+        match next_length_prefixed(&mut rest, "__call_contract") {
+            Ok(arg) => call.push(arg),
+            Err(e) => return call_error(&call, &e.to_string()),
+        }
+    }
+    if let Some(signature) = contract.signature {
+        if let Err(e) = signature.validate(&call[2..]) {
+            return call_error(&call, &format!("{}: address={}", e, to_checksum_hex(&addr)));
+        }
+    }
+    if let Err(e) = trace_event("call", &call, 0, &[]) {
+        return call_error(&call, &e.to_string());
+    }
+    // the status tells the caller the result even if it can't be traced
+    match call_nested(contract.f, &addr, args, args_size) {
+        Ok((c, res)) if c >= 0 => {
+            let _ = trace_event("return", &[], c, &res);
+            __write(res)
+        }
+        Ok((c, _)) => {
+            let _ = trace_event("return", &[], c, &[]);
+            c
+        }
+        Err(e) => {
+            let _ = trace_event("return", &[], -1, &[]);
+            call_error(
+                &call,
+                &format!(
+                    "{}: address={} entry={}",
+                    e,
+                    to_checksum_hex(&addr),
+                    entry_name
+                ),
+            )
+        }
+    }
+}
 
-    FUNC_TABLE.with(|t| match t.borrow().get(&(addr, entry_name)) {
-        Some(f) => {
-            unsafe {
-                if __push_contract_state(addr_ptr, addr_size) != 0 {
-                    panic!("failed to call __push_contract_state");
-                }
-                if __init_args(args, args_size) != 0 {
-                    panic!("failed to call __init_args");
-                }
-            }
-            match f() {
-                c if c >= 0 => {
-                    let res: Vec<u8> = get_return_value().unwrap();
-                    let id = __write(res) as i32;
-                    unsafe {
-                        if __pop_contract_state() != 0 {
-                            panic!("failed to call __pop_contract_state");
-                        }
-                    }
-                    id
-                }
-                c => {
-                    unsafe {
-                        if __pop_contract_state() != 0 {
-                            panic!("failed to call __pop_contract_state");
-                        }
-                    }
-                    c
-                }
+// call_nested runs f for a nested call in a frame of its own, and returns the status and the return value of f.
+// A call whose return value can't be kept in the value table fails before its frame is left, so that its writes are discarded.
+fn call_nested(
+    f: ContractFn,
+    addr: &Address,
+    args: *const u8,
+    args_size: usize,
+) -> Result<(i32, Vec<u8>)> {
+    let frame = NestedCall::enter(addr)?;
+    let ret = unsafe { __init_args(args, args_size) };
+    if ret != 0 {
+        return Err(host_error("__init_args", ret));
+    }
+    let c = f();
+    if c < 0 {
+        frame.leave(false)?;
+        return Ok((c, vec![]));
+    }
+    let res = get_return_value()?;
+    if values_full() {
+        return Err(error::from_str("the value table is full".to_string()));
+    }
+    frame.leave(true)?;
+    Ok((c, res))
+}
+
+// NestedCall is the frame of a nested contract call, which saves the context of the caller while the callee runs.
+// The frame is left when it is dropped, e.g. when the callee panics, and the writes of the callee are discarded
+// unless it succeeded, so that they aren't committed with those of the caller.
+struct NestedCall {
+    left: bool,
+}
+
+impl NestedCall {
+    fn enter(addr: &Address) -> Result<NestedCall> {
+        push_contract_state(addr)?;
+        Ok(NestedCall { left: false })
+    }
+
+    fn leave(mut self, succeeded: bool) -> Result<()> {
+        self.left = true;
+        if !succeeded {
+            discard_state()?;
+        }
+        pop_contract_state()
+    }
+}
+
+impl Drop for NestedCall {
+    fn drop(&mut self) {
+        if !self.left {
+            let _ = discard_state();
+            let _ = pop_contract_state();
+        }
+    }
+}
+
+// CALL_ERRORS keeps the last error of a nested contract call in each process until the next transaction begins.
+thread_local!(static CALL_ERRORS: RefCell<HashMap<i32, String>> = RefCell::new(HashMap::new()));
+
+// call_error records why a contract call failed without a status of the callee, e.g. because it was rejected
+// or the emulator failed to run it, and returns the status the caller gets.
+fn call_error(call: &[&[u8]], msg: &str) -> i32 {
+    CALL_ERRORS.with(|t| t.borrow_mut().insert(get_pid(), msg.to_string()));
+    let mut args = call.to_vec();
+    args.push(msg.as_bytes());
+    // the status tells the caller the call failed even if the error can't be traced
+    let _ = trace_event("call_error", &args, -1, &[]);
+    -1
+}
+
+/// last_call_error returns the error of the last nested contract call which failed without a status of the callee
+/// in the current or the last transaction of the process, e.g. `function not found: address=.. entry=..`.
+pub fn last_call_error() -> Option<String> {
+    CALL_ERRORS.with(|t| t.borrow().get(&get_pid()).cloned())
+}
+
+fn clear_call_error() {
+    CALL_ERRORS.with(|t| t.borrow_mut().remove(&get_pid()));
+}
+
+/// InvokeResult is the outcome of a contract function called by `invoke`.
+#[derive(Debug, PartialEq)]
+pub struct InvokeResult<R> {
+    /// status is the code returned by the contract function, negative on failure.
+    pub status: i32,
+    /// value is the decoded return value, or None if the function returned nothing.
+    pub value: Option<R>,
+    /// events are the events emitted during the call.
+    pub events: Vec<Event>,
+    /// gas is the gas used by the call. It is always 0 since the emulator doesn't meter gas.
+    pub gas: u64,
+}
+
+impl<R> InvokeResult<R> {
+    pub fn is_success(&self) -> bool {
+        self.status >= 0
+    }
+}
+
+// invoke calls a registered contract function as `sender`, commits state if it succeeds,
+// and decodes its return value. The writes of a failed call are discarded.
+// It fails if the process is already running a transaction, e.g. in the callback of call_contract,
+// whose pending writes it would replace.
+pub fn invoke<R: FromBytes>(
+    sender: &Address,
+    contract: &Address,
+    entry: &str,
+    args: Args,
+) -> Result<InvokeResult<R>> {
+    let f = match lookup_contract_function(contract, entry) {
+        Some(ContractEntry { f, signature }) => {
+            if let Some(signature) = signature {
+                signature.validate(&args).map_err(|e| {
+                    error::from_str(format!("{}: address={}", e, to_checksum_hex(contract)))
+                })?;
             }
+            f
         }
         None => {
-            panic!("function not found");
+            return Err(error::from_str(format!(
+                "function not found: address={} entry={}",
+                to_checksum_hex(contract),
+                entry
+            )))
         }
+    };
+    if in_transaction() {
+        return Err(error::from_str(format!(
+            "invoke: the process is already running a transaction: address={} entry={}",
+            to_checksum_hex(contract),
+            entry
+        )));
+    }
+    let tx = Transaction::begin(Some(contract_address()?));
+    init_contract_address(contract)?;
+    let n_events = get_events()?.len();
+    init_sender(sender)?;
+    for arg in args.iter() {
+        init_push_arg(arg)?;
+    }
+    let mut call: Vec<&[u8]> = vec![contract, entry.as_bytes()];
+    call.extend(args.iter().map(|arg| arg.as_slice()));
+    let res = invoke_function(f, &call, n_events);
+    tx.end()?;
+    res
+}
+
+/// invoke_value invokes a contract function like `invoke`, and returns its return value.
+/// It fails if the contract function fails.
+pub fn invoke_value<R: FromBytes>(
+    sender: &Address,
+    contract: &Address,
+    entry: &str,
+    args: Args,
+) -> Result<Option<R>> {
+    let res = invoke(sender, contract, entry, args)?;
+    if !res.is_success() {
+        let mut msg = format!(
+            "function failed: address={} entry={} status={}",
+            to_checksum_hex(contract),
+            entry,
+            res.status
+        );
+        if let Some(e) = last_call_error() {
+            msg.push_str(&format!(": last call error: {}", e));
+        }
+        return Err(error::from_str(msg));
+    }
+    Ok(res.value)
+}
+
+fn invoke_function<R: FromBytes>(
+    f: ContractFn,
+    call: &[&[u8]],
+    n_events: usize,
+) -> Result<InvokeResult<R>> {
+    trace_event("call", call, 0, &[])?;
+    let status = f();
+    let mut value = None;
+    if status >= 0 {
+        let bs: Vec<u8> = get_return_value()?;
+        trace_event("return", &[], status, &bs)?;
+        commit_state()?;
+        if !bs.is_empty() {
+            value = Some(R::from_bytes(bs)?);
+        }
+    } else {
+        trace_event("return", &[], status, &[])?;
+    }
+    let events = get_events()?.split_off(n_events);
+    Ok(InvokeResult {
+        status,
+        value,
+        events,
+        gas: 0,
     })
 }
 
+fn lookup_contract_function(addr: &Address, name: &str) -> Option<ContractEntry> {
+    FUNC_TABLE.with(|t| t.borrow().get(&(*addr, name.to_string())).cloned())
+}
+
 pub fn register_contract_function(addr: Address, name: String, f: ContractFn) {
     FUNC_TABLE.with(|t| {
-        t.borrow_mut().insert((addr, name), f);
+        t.borrow_mut()
+            .insert((addr, name), ContractEntry { f, signature: None });
     });
 }
 
+/// register_contract_function_with_signature registers a contract function like `register_contract_function`,
+/// and validates the arguments of every call against the signature before dispatching it,
+/// so that a call with a wrong number of arguments or an argument of a wrong width fails with the offending argument.
+pub fn register_contract_function_with_signature(
+    addr: Address,
+    name: String,
+    f: ContractFn,
+    signature: &'static abi::Function,
+) {
+    FUNC_TABLE.with(|t| {
+        t.borrow_mut().insert(
+            (addr, name),
+            ContractEntry {
+                f,
+                signature: Some(signature),
+            },
+        );
+    });
+}
+
+#[deprecated(note = "use set_trace and take_trace instead")]
 pub fn set_debug(b: bool) {
-    unsafe {
-        if __set_debug(if b { 1 } else { 0 }) != 0 {
-            panic!("failed to call __set_debug");
-        }
-    }
+    set_trace(b).expect("failed to call __set_trace");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::address::address_from_label;
+    use crate::args;
     use crate::types::ArgsBuilder;
     use hmcdk::api;
+    use hmemu_codegen::address;
+    use std::sync::{Arc, Barrier};
 
     #[test]
     fn initialize_test() {
@@ -429,7 +1233,11 @@ mod tests {
             init_push_arg("key1".as_bytes()).unwrap();
             init_done().unwrap();
 
-            init_push_arg("key2".as_bytes()).expect_err("expect error");
+            let err = init_push_arg("key2".as_bytes()).expect_err("expect error");
+            assert_eq!(
+                "__init_push_arg: error(-1): the process is already initialized",
+                err.to_string()
+            );
             destroy_process().unwrap();
             Ok(())
         })
@@ -454,7 +1262,7 @@ mod tests {
 
     #[test]
     fn sender_test() {
-        let sender = b"d11234567890ABCDEFFF";
+        let sender = &address_from_label("alice");
         exec_process_with_sender(sender, || {
             let s = api::get_sender()?;
             assert_eq!(&s, sender);
@@ -482,9 +1290,9 @@ mod tests {
 
     #[test]
     fn contract_address_test() {
-        let sender1 = b"00000000000000000001";
-        const CONTRACT_A: Address = *b"00000000000000000010";
-        const CONTRACT_B: Address = *b"00000000000000000011";
+        let sender1 = &address!("0x0000000000000000000000000000000000000001");
+        const CONTRACT_A: Address = address!("0x0000000000000000000000000000000000000010");
+        const CONTRACT_B: Address = address!("0x0000000000000000000000000000000000000011");
 
         fn func_b() -> i32 {
             api::return_value(&api::get_contract_address().unwrap())
@@ -518,6 +1326,29 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn write_set_test() {
+        const CONTRACT: Address = address!("0x0000000000000000000000000000000000000010");
+        run_process(|| {
+            init_contract_address(&CONTRACT)?;
+            api::write_state("a".as_bytes(), &1u8.to_bytes());
+            commit_state()?;
+            init_contract_address(&CONTRACT)?;
+            api::write_state("b".as_bytes(), &2u8.to_bytes());
+            api::write_state("a".as_bytes(), &3u8.to_bytes());
+            let keys: Vec<(Vec<u8>, Option<Vec<u8>>)> = get_write_set()?
+                .into_iter()
+                .map(|c| (c.key, c.before))
+                .collect();
+            assert_eq!(
+                vec![(b"a".to_vec(), Some(vec![1u8])), (b"b".to_vec(), None)],
+                keys
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
     #[test]
     fn process_manager_test() {
         let pid1 = exec_function(|| {
@@ -548,6 +1379,110 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn stale_pid_test() {
+        let pid = run_process(|| {
+            assert!(live_process_count() >= 1);
+            Ok(get_pid())
+        })
+        .unwrap();
+        // the pid of a destroyed process stays invalid even if its slot is reused
+        run_process(|| {
+            let err = attach_process(pid).expect_err("expect error");
+            assert_eq!(
+                format!("__attach_process: error(-1): stale pid {}", pid),
+                err.to_string()
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn forked_process_drop_test() {
+        const CONTRACT: Address = address!("0x0000000000000000000000000000000000000010");
+        // a destroyed process can't be attached any more
+        let destroyed = |pid: i32| match attach_process(pid) {
+            Err(e) => e.to_string().ends_with(&format!("stale pid {}", pid)),
+            Ok(()) => false,
+        };
+
+        let (dropped, rejected, forked) = run_process(|| {
+            register_contract_function(CONTRACT, "noop".to_string(), || 0);
+            let forked = fork_process()?;
+            let dropped = forked.pid();
+            drop(forked);
+            let forked = fork_process()?;
+            let rejected = forked.pid();
+            run_forked_process(forked, || Ok(())).expect_err("expect error");
+            // the current process stays attached
+            assert_eq!(Address::default(), contract_address()?);
+            Ok((dropped, rejected, fork_process()?))
+        })
+        .unwrap();
+        assert!(destroyed(dropped));
+        assert!(destroyed(rejected));
+
+        // a panicking run destroys the process and drops the contract functions of the fork
+        let panicked = forked.pid();
+        std::thread::spawn(move || {
+            catch_unwind(AssertUnwindSafe(|| {
+                run_forked_process(forked, || -> Result<()> { panic!("panic") })
+            }))
+            .expect_err("expect panic");
+            assert_eq!(-1, get_pid());
+            assert!(lookup_contract_function(&CONTRACT, "noop").is_none());
+            run_process(|| Ok(())).unwrap();
+        })
+        .join()
+        .unwrap();
+        assert!(destroyed(panicked));
+    }
+
+    #[test]
+    fn last_error_test() {
+        let err = init_sender(&Address::default()).expect_err("expect error");
+        assert_eq!(
+            "__init_sender: error(-1): no process is attached",
+            err.to_string()
+        );
+        // the error is cleared once it is read
+        assert_eq!(None, last_error());
+        run_process(|| {
+            assert_eq!(-1, unsafe { __set_trace(2) });
+            assert_eq!(Some("unknown trace flag 2".to_string()), last_error());
+            assert_eq!(None, last_error());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn parallel_process_test() {
+        const N: usize = 4;
+        let barrier = Arc::new(Barrier::new(N));
+        let handles: Vec<_> = (0..N as i64)
+            .map(|i| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    exec_process_with_arguments(args![i], || {
+                        api::write_state("key".as_bytes(), &i.to_bytes());
+                        commit_state()?;
+                        // all processes have to run at the same time to pass the barrier
+                        barrier.wait();
+                        assert_eq!(i, api::get_arg::<i64>(0)?);
+                        assert_eq!(i, api::read_state::<i64>("key".as_bytes())?);
+                        Ok(())
+                    })
+                    .unwrap();
+                })
+            })
+            .collect();
+        for h in handles.into_iter() {
+            h.join().unwrap();
+        }
+    }
+
     #[test]
     fn exec_function_assert_test() {
         catch_unwind(|| {
@@ -561,10 +1496,71 @@ mod tests {
         exec_function(|| Ok(())).unwrap();
     }
 
+    #[test]
+    fn value_table_test() {
+        let sender = address!("0x0000000000000000000000000000000000000001");
+        run_process(|| {
+            let mut buf = [0u8; 8];
+            let id = call_contract(&sender, args![], || {
+                let id = __write(vec![1, 2, 3]);
+                assert_eq!(3, __read(id as usize, 0, buf.as_mut_ptr(), buf.len()));
+                assert_eq!([1, 2, 3], buf[..3]);
+                assert_eq!(2, __read(id as usize, 1, buf.as_mut_ptr(), buf.len()));
+                assert_eq!([2, 3], buf[..2]);
+                assert_eq!(0, __read(id as usize, 3, buf.as_mut_ptr(), buf.len()));
+                assert_eq!(-1, __read(id as usize + 1, 0, buf.as_mut_ptr(), buf.len()));
+                Ok(id)
+            })?;
+            // values don't outlive the transaction
+            assert_eq!(-1, __read(id as usize, 0, buf.as_mut_ptr(), buf.len()));
+
+            for _ in 0..MAX_VALUES {
+                assert!(__write(vec![]) >= 0);
+            }
+            assert_eq!(-1, __write(vec![]));
+            clear()?;
+            assert_eq!(0, __write(vec![]));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(0, value_table_len());
+    }
+
+    #[test]
+    fn value_table_full_test() {
+        const SENDER: Address = address!("0x0000000000000000000000000000000000000001");
+        const CONTRACT: Address = address!("0x0000000000000000000000000000000000000010");
+
+        fn write() -> i32 {
+            api::write_state("key".as_bytes(), &[1]);
+            api::return_value(&[])
+        }
+
+        run_process(|| {
+            register_contract_function(CONTRACT, "write".to_string(), write);
+            call_contract(&SENDER, args![], || {
+                for _ in 0..MAX_VALUES {
+                    assert!(__write(vec![]) >= 0);
+                }
+                api::call_contract::<Vec<u8>>(&CONTRACT, "write".as_bytes(), vec![])
+                    .expect_err("expect error");
+                assert_eq!(
+                    Some("the value table is full: address=0x0000000000000000000000000000000000000010 entry=write".to_string()),
+                    last_call_error()
+                );
+                Ok(())
+            })?;
+            // the callee's write is not committed with the caller's
+            assert!(get_state()?.is_empty());
+            Ok(())
+        })
+        .unwrap();
+    }
+
     #[test]
     fn call_contract_test() {
-        let sender1 = b"00000000000000000001";
-        let sender2 = b"00000000000000000002";
+        let sender1 = &address!("0x0000000000000000000000000000000000000001");
+        let sender2 = &address!("0x0000000000000000000000000000000000000002");
         let key = "key".as_bytes();
         let value = "value".as_bytes();
         run_process(|| {
@@ -607,6 +1603,262 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn invoke_test() {
+        const SENDER: Address = address!("0x0000000000000000000000000000000000000001");
+        const CONTRACT: Address = address!("0x0000000000000000000000000000000000000010");
+
+        fn add() -> i32 {
+            let x: i64 = api::get_arg(0).unwrap();
+            let y: i64 = api::get_arg(1).unwrap();
+            api::write_state("sum".as_bytes(), &(x + y).to_bytes());
+            api::emit_event("added", &api::get_sender().unwrap()).unwrap();
+            api::return_value(&(x + y).to_bytes())
+        }
+        fn noop() -> i32 {
+            0
+        }
+        fn fail() -> i32 {
+            api::write_state("sum".as_bytes(), &0i64.to_bytes());
+            -1
+        }
+        fn panic() -> i32 {
+            let _: i64 = api::get_arg(0).unwrap();
+            0
+        }
+
+        run_process(|| {
+            register_contract_function(CONTRACT, "add".to_string(), add);
+            register_contract_function(CONTRACT, "noop".to_string(), noop);
+            register_contract_function(CONTRACT, "fail".to_string(), fail);
+            register_contract_function(CONTRACT, "panic".to_string(), panic);
+
+            let res = invoke::<i64>(&SENDER, &CONTRACT, "add", args![1i64, 2i64])?;
+            assert!(res.is_success());
+            assert_eq!(Some(3), res.value);
+            assert_eq!(
+                vec![Event {
+                    name: "added".to_string(),
+                    value: SENDER.to_vec()
+                }],
+                res.events
+            );
+            assert_eq!(0, res.gas);
+            assert_eq!(
+                Some(&3i64.to_bytes()[..]),
+                get_state()?.get(&CONTRACT, "sum".as_bytes())
+            );
+            // the contract address of the process is restored
+            assert_eq!(Address::default(), contract_address()?);
+
+            // a panicking contract function leaves the process ready for the next call
+            catch_unwind(AssertUnwindSafe(|| {
+                invoke::<i64>(&SENDER, &CONTRACT, "panic", args![])
+            }))
+            .expect_err("expect panic");
+            assert_eq!(Address::default(), contract_address()?);
+            assert!(invoke::<i64>(&SENDER, &CONTRACT, "noop", args![])?.is_success());
+
+            let res = invoke::<i64>(&SENDER, &CONTRACT, "noop", args![])?;
+            assert_eq!(None, res.value);
+            assert!(res.events.is_empty());
+
+            let res = invoke::<i64>(&SENDER, &CONTRACT, "fail", args![])?;
+            assert!(!res.is_success());
+            assert_eq!(None, res.value);
+
+            invoke::<i64>(&SENDER, &CONTRACT, "unknown", args![]).expect_err("expect error");
+
+            assert_eq!(
+                Some(5),
+                invoke_value::<i64>(&SENDER, &CONTRACT, "add", args![2i64, 3i64])?
+            );
+            let err = invoke_value::<i64>(&SENDER, &CONTRACT, "fail", args![])
+                .expect_err("expect error");
+            assert_eq!(
+                "function failed: address=0x0000000000000000000000000000000000000010 entry=fail status=-1",
+                err.to_string()
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn discard_test() {
+        const SENDER: Address = address!("0x0000000000000000000000000000000000000001");
+        const CONTRACT_A: Address = address!("0x0000000000000000000000000000000000000010");
+        const CONTRACT_B: Address = address!("0x0000000000000000000000000000000000000011");
+
+        fn fail_a() -> i32 {
+            api::write_state("a".as_bytes(), &1u8.to_bytes());
+            let _: Vec<u8> = api::call_contract(&CONTRACT_B, "write_b".as_bytes(), vec![]).unwrap();
+            -1
+        }
+        fn panic_a() -> i32 {
+            let _: Vec<u8> = api::call_contract(&CONTRACT_B, "write_b".as_bytes(), vec![]).unwrap();
+            let _: Vec<u8> = api::call_contract(&CONTRACT_B, "panic_b".as_bytes(), vec![]).unwrap();
+            0
+        }
+        fn call_fail_b() -> i32 {
+            api::call_contract::<Vec<u8>>(&CONTRACT_B, "fail_b".as_bytes(), vec![])
+                .expect_err("expect error");
+            api::return_value(&[])
+        }
+        fn write_b() -> i32 {
+            api::write_state("b".as_bytes(), &1u8.to_bytes());
+            api::return_value(&[])
+        }
+        fn fail_b() -> i32 {
+            api::write_state("b".as_bytes(), &2u8.to_bytes());
+            -1
+        }
+        fn panic_b() -> i32 {
+            api::write_state("b".as_bytes(), &3u8.to_bytes());
+            panic!("panic_b");
+        }
+        fn noop() -> i32 {
+            0
+        }
+
+        run_process(|| {
+            register_contract_function(CONTRACT_A, "fail_a".to_string(), fail_a);
+            register_contract_function(CONTRACT_A, "panic_a".to_string(), panic_a);
+            register_contract_function(CONTRACT_A, "call_fail_b".to_string(), call_fail_b);
+            register_contract_function(CONTRACT_A, "noop".to_string(), noop);
+            register_contract_function(CONTRACT_B, "write_b".to_string(), write_b);
+            register_contract_function(CONTRACT_B, "fail_b".to_string(), fail_b);
+            register_contract_function(CONTRACT_B, "panic_b".to_string(), panic_b);
+
+            // the writes of a failed transaction and of its nested calls are not committed by the next one
+            let res = invoke::<Vec<u8>>(&SENDER, &CONTRACT_A, "fail_a", args![])?;
+            assert!(!res.is_success());
+            assert!(invoke::<Vec<u8>>(&SENDER, &CONTRACT_A, "noop", args![])?.is_success());
+            assert!(get_state()?.is_empty());
+
+            // a panicking nested call leaves its frame, and its transaction is discarded
+            catch_unwind(AssertUnwindSafe(|| {
+                invoke::<Vec<u8>>(&SENDER, &CONTRACT_A, "panic_a", args![])
+            }))
+            .expect_err("expect panic");
+            assert_eq!(Address::default(), contract_address()?);
+            assert!(invoke::<Vec<u8>>(&SENDER, &CONTRACT_A, "noop", args![])?.is_success());
+            assert!(get_state()?.is_empty());
+
+            // the writes of a failed nested call are discarded even if the caller succeeds
+            let res = invoke::<Vec<u8>>(&SENDER, &CONTRACT_A, "call_fail_b", args![])?;
+            assert!(res.is_success());
+            assert!(get_state()?.is_empty());
+
+            // invoke doesn't replace the pending writes of a running transaction
+            call_contract(&SENDER, args![], || {
+                api::write_state("pending".as_bytes(), &[]);
+                invoke::<Vec<u8>>(&SENDER, &CONTRACT_A, "noop", args![]).expect_err("expect error");
+                Ok(())
+            })?;
+            assert_eq!(
+                Some(&[][..]),
+                get_state()?.get(&Address::default(), "pending".as_bytes())
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn call_error_test() {
+        const SENDER: Address = address!("0x0000000000000000000000000000000000000001");
+        const CONTRACT: Address = address!("0x0000000000000000000000000000000000000010");
+
+        fn call_missing() -> i32 {
+            match api::call_contract::<Vec<u8>>(&CONTRACT, "missing".as_bytes(), vec![]) {
+                Ok(_) => 0,
+                Err(_) => -1,
+            }
+        }
+
+        run_process(|| {
+            register_contract_function(CONTRACT, "call_missing".to_string(), call_missing);
+
+            // a call which can't be dispatched fails instead of panicking, and the error is kept
+            call_contract(&SENDER, args![], || {
+                api::call_contract::<Vec<u8>>(&CONTRACT, &[0xff], vec![])
+                    .expect_err("expect error");
+                assert!(last_call_error()
+                    .unwrap()
+                    .starts_with("__call_contract: invalid entry: "));
+                Ok(())
+            })?;
+            let err = invoke_value::<Vec<u8>>(&SENDER, &CONTRACT, "call_missing", args![])
+                .expect_err("expect error");
+            assert_eq!(
+                "function failed: address=0x0000000000000000000000000000000000000010 entry=call_missing status=-1: last call error: function not found: address=0x0000000000000000000000000000000000000010 entry=missing",
+                err.to_string()
+            );
+            assert_eq!(
+                Some("function not found: address=0x0000000000000000000000000000000000000010 entry=missing".to_string()),
+                last_call_error()
+            );
+
+            // the error is cleared when the next transaction begins
+            call_contract(&SENDER, args![], || Ok(()))?;
+            assert_eq!(None, last_call_error());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn signature_test() {
+        const SENDER: Address = address!("0x0000000000000000000000000000000000000001");
+        const CONTRACT: Address = address!("0x0000000000000000000000000000000000000010");
+        static ADD: abi::Function = abi::Function {
+            name: "add",
+            args: &["i64", "i64"],
+            returns: Some("i64"),
+            arity: abi::Arity::Exact,
+        };
+
+        fn add() -> i32 {
+            let x: i64 = api::get_arg(0).unwrap();
+            let y: i64 = api::get_arg(1).unwrap();
+            api::return_value(&(x + y).to_bytes())
+        }
+
+        run_process(|| {
+            register_contract_function_with_signature(CONTRACT, "add".to_string(), add, &ADD);
+            assert_eq!(
+                Some(3),
+                invoke_value::<i64>(&SENDER, &CONTRACT, "add", args![1i64, 2i64])?
+            );
+            let err = invoke::<i64>(&SENDER, &CONTRACT, "add", args![1i64, 2i32])
+                .expect_err("expect error");
+            assert_eq!(
+                "add(i64, i64): argument 1 (i64) must be 8 bytes, got 4: address=0x0000000000000000000000000000000000000010",
+                err.to_string()
+            );
+
+            // a nested call is validated too, and fails with the error in the trace
+            set_trace(true)?;
+            let res = call_contract(&SENDER, args![], || {
+                Ok(api::call_contract::<i64>(&CONTRACT, b"add", vec![&1i64.to_bytes()]).is_err())
+            });
+            assert!(res?);
+            let trace = take_trace()?;
+            let e = trace
+                .iter()
+                .find(|e| e.name == "call_error")
+                .expect("expect call_error");
+            assert_eq!(-1, e.result);
+            assert_eq!(
+                "add(i64, i64): expected 2 arguments, got 1: address=0x0000000000000000000000000000000000000010",
+                String::from_utf8_lossy(e.args.last().unwrap())
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
     #[test]
     fn run_process_test() {
         let key = "key".as_bytes();
@@ -634,10 +1886,10 @@ mod tests {
 
     #[test]
     fn call_external_contract_test() {
-        const SENDER: Address = *b"00000000000000000001";
-        const CONTRACT_A: Address = *b"00000000000000000010";
-        const CONTRACT_B: Address = *b"00000000000000000011";
-        const CONTRACT_C: Address = *b"00000000000000000012";
+        const SENDER: Address = address!("0x0000000000000000000000000000000000000001");
+        const CONTRACT_A: Address = address!("0x0000000000000000000000000000000000000010");
+        const CONTRACT_B: Address = address!("0x0000000000000000000000000000000000000011");
+        const CONTRACT_C: Address = address!("0x0000000000000000000000000000000000000012");
 
         // 1. call external contract simply, and ensure returned value matches expected
         {
@@ -0,0 +1,275 @@
+//! Property-based testing of contracts with proptest, enabled by the `proptest` feature.
+//!
+//! ```ignore
+//! use hmemu::proptest::{self as hp, Runner};
+//! use proptest::prelude::*;
+//!
+//! Runner::default()
+//!     .run(&(hp::address(), hp::args((any::<i64>(), any::<i64>()))), |(sender, args)| {
+//!         hmemu::call_contract(&sender, args, || {
+//!             contract_func()?;
+//!             Ok(())
+//!         })
+//!     })
+//!     .unwrap();
+//! ```
+use crate::process::*;
+use crate::trace::render_tree;
+use crate::types::Args;
+use ::proptest::prelude::*;
+use ::proptest::sample;
+use ::proptest::test_runner::{Config, TestCaseError, TestError, TestRunner};
+use hmcdk::error;
+use hmcdk::prelude::*;
+use std::fmt::Debug;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// address generates arbitrary addresses.
+pub fn address() -> impl Strategy<Value = Address> {
+    any::<[u8; 20]>()
+}
+
+/// address_of picks one of the given addresses, e.g. one of a few known accounts as the sender.
+pub fn address_of(addrs: Vec<Address>) -> impl Strategy<Value = Address> {
+    sample::select(addrs)
+}
+
+/// ToArgs converts a tuple of values implementing `ToBytes` into `Args`.
+pub trait ToArgs {
+    fn to_args(&self) -> Args;
+}
+
+macro_rules! impl_to_args {
+    ($($t:ident),*) => {
+        impl<$($t: ToBytes),*> ToArgs for ($($t,)*) {
+            #[allow(non_snake_case)]
+            fn to_args(&self) -> Args {
+                let ($($t,)*) = self;
+                vec![$($t.to_bytes()),*]
+            }
+        }
+    };
+}
+
+impl_to_args!();
+impl_to_args!(A);
+impl_to_args!(A, B);
+impl_to_args!(A, B, C);
+impl_to_args!(A, B, C, D);
+impl_to_args!(A, B, C, D, E);
+impl_to_args!(A, B, C, D, E, F);
+impl_to_args!(A, B, C, D, E, F, G);
+impl_to_args!(A, B, C, D, E, F, G, H);
+
+/// args generates `Args` from a strategy of tuples of `ToBytes` values.
+/// Failing cases are shrunk on the tuple, so the minimal arguments are minimal as values, not as bytes.
+pub fn args<S>(values: S) -> impl Strategy<Value = Args>
+where
+    S: Strategy,
+    S::Value: ToArgs,
+{
+    values.prop_map(|v| v.to_args())
+}
+
+/// Runner runs each case of a property test in its own process, and shrinks failing inputs.
+///
+/// Without a setup, every case starts from a fresh process.
+/// With a setup, the setup runs once and every case runs in a fork of its process.
+#[derive(Default)]
+pub struct Runner {
+    config: Config,
+    setup: Option<Box<dyn FnOnce() -> Result<()>>>,
+}
+
+impl Runner {
+    pub fn new(config: Config) -> Self {
+        Runner {
+            config,
+            setup: None,
+        }
+    }
+
+    /// setup sets a function preparing the process every case is forked from.
+    /// Only committed state is carried over to the cases.
+    pub fn setup<F: FnOnce() -> Result<()> + 'static>(mut self, f: F) -> Self {
+        self.setup = Some(Box::new(f));
+        self
+    }
+
+    /// run runs test for values generated by strategy.
+    /// A case fails if test returns an error or panics, and the error reports the minimal failing input
+    /// with the trace of the host calls it made.
+    pub fn run<S, F>(self, strategy: &S, test: F) -> Result<()>
+    where
+        S: Strategy,
+        S::Value: Debug,
+        F: Fn(S::Value) -> Result<()>,
+    {
+        let mut runner = TestRunner::new(self.config);
+        match self.setup {
+            None => {
+                let result = runner.run(strategy, |v| {
+                    to_case_result(run_process(AssertUnwindSafe(|| run_case(|| test(v)))))
+                });
+                report(result, |v| {
+                    run_process(AssertUnwindSafe(|| trace_case(|| test(v))))
+                })
+            }
+            Some(setup) => run_process(AssertUnwindSafe(|| {
+                setup()?;
+                let result = runner.run(strategy, |v| {
+                    to_case_result(run_in_fork(|| run_case(|| test(v))))
+                });
+                report(result, |v| run_in_fork(|| trace_case(|| test(v))))
+            })),
+        }
+    }
+}
+
+// run_in_fork runs f in a fork of the current process.
+fn run_in_fork<T, F: FnOnce() -> Result<T>>(f: F) -> Result<T> {
+    let forked = fork_process()?;
+    suspend_process(|| run_forked_process(forked, AssertUnwindSafe(f))).and_then(|res| res)
+}
+
+// trace_case runs the case again with tracing enabled, and renders the host calls it made.
+fn trace_case<F: FnOnce() -> Result<()>>(f: F) -> Result<String> {
+    set_trace(true)?;
+    let _ = run_case(f);
+    Ok(render_tree(&take_trace()?))
+}
+
+fn report<V: Debug, F: FnOnce(V) -> Result<String>>(
+    result: std::result::Result<(), TestError<V>>,
+    trace: F,
+) -> Result<()> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(TestError::Abort(reason)) => Err(error::from_str(format!("test aborted: {}", reason))),
+        Err(TestError::Fail(reason, value)) => {
+            let mut msg = format!(
+                "test failed: {}; minimal failing input: {:?}",
+                reason, value
+            );
+            match trace(value) {
+                Ok(trace) if trace.is_empty() => {}
+                Ok(trace) => {
+                    msg.push_str(&format!("\ntrace of the minimal failing input:\n{}", trace))
+                }
+                Err(e) => msg.push_str(&format!(
+                    "\nfailed to trace the minimal failing input: {}",
+                    e
+                )),
+            }
+            Err(error::from_str(msg))
+        }
+    }
+}
+
+// run_case turns a panic of the case into an error, so that the process of the case is still destroyed.
+fn run_case<F: FnOnce() -> Result<()>>(f: F) -> Result<()> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(e) => Err(error::from_str(format!("panicked: {}", panic_message(&*e)))),
+    }
+}
+
+fn to_case_result(res: Result<()>) -> std::result::Result<(), TestCaseError> {
+    res.map_err(|e| TestCaseError::fail(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::address_from_label;
+    use hmcdk::api;
+
+    #[test]
+    fn args_test() {
+        assert_eq!(
+            crate::args![1i64, true, "name"],
+            (1i64, true, "name").to_args()
+        );
+        assert_eq!(Args::new(), ().to_args());
+    }
+
+    #[test]
+    fn runner_test() {
+        Runner::default()
+            .run(
+                &(address(), args((any::<i64>(), any::<u32>()))),
+                |(sender, args)| {
+                    let expected = args.clone();
+                    call_contract(&sender, args, || {
+                        assert_eq!(sender, api::get_sender()?);
+                        assert_eq!(expected[0], api::get_arg::<Vec<u8>>(0)?);
+                        assert_eq!(expected[1], api::get_arg::<Vec<u8>>(1)?);
+                        Ok(())
+                    })
+                },
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn shrink_test() {
+        let err = Runner::default()
+            .run(&(0i64..10000), |v| {
+                assert!(v < 100, "too large");
+                Ok(())
+            })
+            .expect_err("expect error");
+        assert!(err.to_string().contains("too large"), "{}", err);
+        assert!(
+            err.to_string().ends_with("minimal failing input: 100"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn trace_test() {
+        let err = Runner::default()
+            .run(&(0u8..100), |v| {
+                let sender = address_from_label("alice");
+                call_contract(&sender, Args::new(), || {
+                    api::write_state("key".as_bytes(), &[v]);
+                    Ok(())
+                })?;
+                assert!(v < 10, "too large");
+                Ok(())
+            })
+            .expect_err("expect error");
+        assert!(
+            err.to_string().ends_with(
+                "minimal failing input: 10\ntrace of the minimal failing input:\nwrite_state(\"key\", 0x0a) -> 0\ncommit_state(0x0000000000000000000000000000000000000000, \"key\", 0x0a) -> 0\n"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn setup_test() {
+        let alice = address_from_label("alice");
+        let sender = address_of(vec![alice, address_from_label("bob")]);
+        Runner::new(Config::with_cases(32))
+            .setup(move || {
+                call_contract(&alice, Args::new(), || {
+                    api::write_state("key".as_bytes(), "setup".as_bytes());
+                    Ok(())
+                })
+            })
+            .run(&(sender, any::<u8>()), |(sender, v)| {
+                assert_eq!("setup", api::read_state::<String>("key".as_bytes())?);
+                call_contract(&sender, Args::new(), || {
+                    api::write_state("key".as_bytes(), &[v]);
+                    Ok(())
+                })?;
+                // writes of the other cases are invisible
+                assert_eq!(vec![v], api::read_state::<Vec<u8>>("key".as_bytes())?);
+                Ok(())
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,712 @@
+//! Scenarios are test cases written in YAML or JSON as steps run against the emulator,
+//! so that they can be written without Rust.
+//!
+//! ```yaml
+//! name: transfer
+//! steps:
+//!   - deploy:
+//!       address: addr:0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a
+//!   - call:
+//!       sender: addr:0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c
+//!       entry: transfer
+//!       args: [addr:0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed, u64:30]
+//!   - expect-return: u64:70
+//!   - expect-event: { name: transfer, value: u64:30 }
+//!   - expect-state: { key: str:total, value: u64:100 }
+//! ```
+//!
+//! `deploy` registers the contract functions exported in the binary at the address, see `deploy_crate_contract`,
+//! and fails if there are none, or loads a contract plugin given by `plugin`, whose path is relative to the scenario file.
+//! `call` invokes an entry of the contract deployed last unless `contract` is given, and commits its writes if it succeeds.
+//! It fails if the contract function fails or the call is rejected, e.g. for invalid arguments,
+//! unless `expect-failure: true` is given.
+//! `expect-return` and `expect-event` check the return value and the events of the last call,
+//! and `expect-state` checks the committed state, where a null value expects the key to be absent.
+//!
+//! Values are typed as `<type>:<value>`: an integer type such as `i64:5`, `bool:true`, `addr:0x..`, `str:..` or `hex:0x..`.
+use crate::abi::format_value;
+use crate::address::{from_hex, to_checksum_hex};
+use crate::export::deploy_crate_contract;
+use crate::plugin::Plugin;
+use crate::process::*;
+use hmcdk::error;
+use hmcdk::prelude::*;
+use serde_json::{Map, Value as Json};
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+/// Scenario is a test case of steps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+/// Step is a step of a scenario, written as a mapping of a single key such as `call`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Deploy {
+        address: Address,
+        plugin: Option<PathBuf>,
+    },
+    Call {
+        contract: Option<Address>,
+        sender: Option<Address>,
+        entry: String,
+        args: Vec<TypedValue>,
+        expect_failure: bool,
+    },
+    ExpectReturn(Option<TypedValue>),
+    ExpectEvent {
+        name: String,
+        value: Option<TypedValue>,
+    },
+    ExpectState {
+        contract: Option<Address>,
+        key: TypedValue,
+        value: Option<TypedValue>,
+    },
+}
+
+/// TypedValue is a value written as `<type>:<value>`, with the bytes it is encoded to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedValue {
+    pub text: String,
+    pub bytes: Vec<u8>,
+}
+
+macro_rules! parse_int {
+    ($ty:expr, $value:expr, $($t:ty),*) => {
+        match $ty {
+            $(stringify!($t) => return $value.parse::<$t>().map(|v| v.to_bytes()).ok(),)*
+            _ => {}
+        }
+    };
+}
+
+impl TypedValue {
+    pub fn parse(text: &str) -> Result<Self> {
+        let bytes = text
+            .find(':')
+            .and_then(|pos| encode(&text[..pos], &text[pos + 1..]))
+            .ok_or_else(|| {
+                error::from_str(format!(
+                    "invalid value `{}`: expected a typed value like i64:5, bool:true, addr:0x.., str:.. or hex:0x..",
+                    text
+                ))
+            })?;
+        Ok(TypedValue {
+            text: text.to_string(),
+            bytes,
+        })
+    }
+
+    // format renders bytes as a value of the same type, e.g. `u64:99` for `u64:100`.
+    fn format(&self, bs: &[u8]) -> String {
+        let ty = &self.text[..self.text.find(':').unwrap_or(0)];
+        let abi_type = match ty {
+            "addr" => "Address",
+            "str" => "String",
+            ty => ty,
+        };
+        let value = format_value(abi_type, bs);
+        match ty {
+            "hex" => value,
+            // a string is rendered quoted
+            "str" => format!("str:{}", value.trim_matches('"')),
+            ty if value.starts_with("0x") && ty != "addr" => value,
+            ty => format!("{}:{}", ty, value),
+        }
+    }
+}
+
+fn encode(ty: &str, value: &str) -> Option<Vec<u8>> {
+    parse_int!(ty, value, i8, u8, i16, u16, i32, u32, i64, u64, i128, u128);
+    match ty {
+        "bool" => match value {
+            "true" => Some(vec![1]),
+            "false" => Some(vec![0]),
+            _ => None,
+        },
+        "addr" => from_hex(value).ok().map(|a| a.to_vec()),
+        "str" => Some(value.as_bytes().to_vec()),
+        "hex" => {
+            let hex = value.strip_prefix("0x").unwrap_or(value);
+            // an odd number of digits leaves a digit out of range
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect()
+        }
+        _ => None,
+    }
+}
+
+impl Scenario {
+    pub fn from_yaml(s: &str) -> Result<Self> {
+        let json: Json = serde_yaml::from_str(s).map_err(|e| error::from_str(e.to_string()))?;
+        Self::from_value(&json)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self> {
+        let json: Json = serde_json::from_str(s).map_err(|e| error::from_str(e.to_string()))?;
+        Self::from_value(&json)
+    }
+
+    /// from_file reads a scenario from a `.json` file, or a YAML file otherwise.
+    /// The paths of plugins are resolved relative to the file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| error::from_str(format!("{}: {}", path.display(), e)))?;
+        let mut scenario = match path.extension() {
+            Some(ext) if ext == "json" => Self::from_json(&s),
+            _ => Self::from_yaml(&s),
+        }
+        .map_err(|e| error::from_str(format!("{}: {}", path.display(), e)))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for step in scenario.steps.iter_mut() {
+            if let Step::Deploy {
+                plugin: Some(ref mut plugin),
+                ..
+            } = step
+            {
+                *plugin = dir.join(&plugin);
+            }
+        }
+        if scenario.name.is_empty() {
+            scenario.name = path.display().to_string();
+        }
+        Ok(scenario)
+    }
+
+    fn from_value(json: &Json) -> Result<Self> {
+        let obj = object(json, "scenario")?;
+        let name = match obj.get("name") {
+            Some(name) => string(name, "name")?,
+            None => String::new(),
+        };
+        let steps = match obj.get("steps") {
+            Some(Json::Array(steps)) => steps
+                .iter()
+                .enumerate()
+                .map(|(i, step)| {
+                    parse_step(step).map_err(|e| error::from_str(format!("step {}: {}", i + 1, e)))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => {
+                return Err(error::from_str(
+                    "scenario: expected a list of steps".to_string(),
+                ))
+            }
+        };
+        Ok(Scenario { name, steps })
+    }
+
+    /// run runs the steps in a fresh process. A failed step doesn't stop the following steps.
+    pub fn run(&self) -> Result<Report> {
+        let steps = run_process(AssertUnwindSafe(|| {
+            let mut runner = Runner::default();
+            Ok(self
+                .steps
+                .iter()
+                .map(|step| StepReport {
+                    step: step.to_string(),
+                    error: runner.run(step).err().map(|e| e.to_string()),
+                })
+                .collect())
+        }))?;
+        Ok(Report {
+            name: self.name.clone(),
+            steps,
+        })
+    }
+}
+
+/// run_scenario runs the scenario in a YAML or JSON file, and reports the result of every step.
+pub fn run_scenario<P: AsRef<Path>>(path: P) -> Result<Report> {
+    Scenario::from_file(path.as_ref())?.run()
+}
+
+fn object<'a>(json: &'a Json, what: &str) -> Result<&'a Map<String, Json>> {
+    match json {
+        Json::Object(obj) => Ok(obj),
+        _ => Err(error::from_str(format!("{}: expected a mapping", what))),
+    }
+}
+
+fn string(json: &Json, what: &str) -> Result<String> {
+    match json {
+        Json::String(s) => Ok(s.clone()),
+        _ => Err(error::from_str(format!("{}: expected a string", what))),
+    }
+}
+
+fn typed(json: &Json, what: &str) -> Result<TypedValue> {
+    TypedValue::parse(&string(json, what)?)
+}
+
+fn optional_typed(json: Option<&Json>, what: &str) -> Result<Option<TypedValue>> {
+    match json {
+        None | Some(Json::Null) => Ok(None),
+        Some(json) => typed(json, what).map(Some),
+    }
+}
+
+fn address(json: Option<&Json>, what: &str) -> Result<Option<Address>> {
+    match json {
+        None => Ok(None),
+        Some(json) => {
+            let v = typed(json, what)?;
+            if !v.text.starts_with("addr:") {
+                return Err(error::from_str(format!(
+                    "{}: expected an address like addr:0x..",
+                    what
+                )));
+            }
+            Ok(Some(from_hex(&v.text[5..])?))
+        }
+    }
+}
+
+fn check_keys(obj: &Map<String, Json>, what: &str, keys: &[&str]) -> Result<()> {
+    match obj.keys().find(|k| !keys.contains(&k.as_str())) {
+        Some(k) => Err(error::from_str(format!("{}: unknown field `{}`", what, k))),
+        None => Ok(()),
+    }
+}
+
+fn parse_step(json: &Json) -> Result<Step> {
+    let obj = object(json, "step")?;
+    let (kind, body) = match obj.iter().next() {
+        Some(entry) if obj.len() == 1 => entry,
+        _ => {
+            return Err(error::from_str(
+                "expected one of deploy, call, expect-return, expect-event or expect-state"
+                    .to_string(),
+            ))
+        }
+    };
+    match kind.as_str() {
+        "deploy" => {
+            let d = object(body, kind)?;
+            check_keys(d, kind, &["address", "plugin"])?;
+            Ok(Step::Deploy {
+                address: address(d.get("address"), "deploy.address")?.ok_or_else(|| {
+                    error::from_str("deploy: missing field `address`".to_string())
+                })?,
+                plugin: match d.get("plugin") {
+                    Some(p) => Some(PathBuf::from(string(p, "deploy.plugin")?)),
+                    None => None,
+                },
+            })
+        }
+        "call" => {
+            let c = object(body, kind)?;
+            check_keys(
+                c,
+                kind,
+                &["contract", "sender", "entry", "args", "expect-failure"],
+            )?;
+            let args = match c.get("args") {
+                None => Vec::new(),
+                Some(Json::Array(args)) => args
+                    .iter()
+                    .map(|a| typed(a, "call.args"))
+                    .collect::<Result<Vec<_>>>()?,
+                Some(_) => return Err(error::from_str("call.args: expected a list".to_string())),
+            };
+            Ok(Step::Call {
+                contract: address(c.get("contract"), "call.contract")?,
+                sender: address(c.get("sender"), "call.sender")?,
+                entry: match c.get("entry") {
+                    Some(entry) => string(entry, "call.entry")?,
+                    None => return Err(error::from_str("call: missing field `entry`".to_string())),
+                },
+                args,
+                expect_failure: match c.get("expect-failure") {
+                    None => false,
+                    Some(Json::Bool(b)) => *b,
+                    Some(_) => {
+                        return Err(error::from_str(
+                            "call.expect-failure: expected true or false".to_string(),
+                        ))
+                    }
+                },
+            })
+        }
+        "expect-return" => Ok(Step::ExpectReturn(optional_typed(Some(body), kind)?)),
+        "expect-event" => {
+            let e = object(body, kind)?;
+            check_keys(e, kind, &["name", "value"])?;
+            Ok(Step::ExpectEvent {
+                name: match e.get("name") {
+                    Some(name) => string(name, "expect-event.name")?,
+                    None => {
+                        return Err(error::from_str(
+                            "expect-event: missing field `name`".to_string(),
+                        ))
+                    }
+                },
+                value: optional_typed(e.get("value"), "expect-event.value")?,
+            })
+        }
+        "expect-state" => {
+            let s = object(body, kind)?;
+            check_keys(s, kind, &["contract", "key", "value"])?;
+            Ok(Step::ExpectState {
+                contract: address(s.get("contract"), "expect-state.contract")?,
+                key: match s.get("key") {
+                    Some(key) => typed(key, "expect-state.key")?,
+                    None => {
+                        return Err(error::from_str(
+                            "expect-state: missing field `key`".to_string(),
+                        ))
+                    }
+                },
+                value: optional_typed(s.get("value"), "expect-state.value")?,
+            })
+        }
+        other => Err(error::from_str(format!(
+            "unknown step `{}`, expected one of deploy, call, expect-return, expect-event or expect-state",
+            other
+        ))),
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Deploy { address, plugin } => {
+                write!(f, "deploy {}", to_checksum_hex(address))?;
+                if let Some(plugin) = plugin {
+                    write!(f, " from {}", plugin.display())?;
+                }
+                Ok(())
+            }
+            Step::Call { entry, args, .. } => {
+                let args: Vec<&str> = args.iter().map(|a| a.text.as_str()).collect();
+                write!(f, "call {}({})", entry, args.join(", "))
+            }
+            Step::ExpectReturn(value) => write!(f, "expect-return {}", text(value)),
+            Step::ExpectEvent { name, value } => write!(f, "expect-event {} {}", name, text(value)),
+            Step::ExpectState { key, value, .. } => {
+                write!(f, "expect-state {} = {}", key.text, text(value))
+            }
+        }
+    }
+}
+
+fn text(value: &Option<TypedValue>) -> &str {
+    value.as_ref().map_or("null", |v| v.text.as_str())
+}
+
+/// Report is the result of every step of a scenario.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub name: String,
+    pub steps: Vec<StepReport>,
+}
+
+/// StepReport is the result of a step, with the reason if it failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepReport {
+    pub step: String,
+    pub error: Option<String>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|s| s.error.is_none())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "scenario {}", self.name)?;
+        for (i, s) in self.steps.iter().enumerate() {
+            match s.error {
+                None => writeln!(f, "  ok   {}. {}", i + 1, s.step)?,
+                Some(ref e) => writeln!(f, "  FAIL {}. {}: {}", i + 1, s.step, e)?,
+            }
+        }
+        let passed = self.steps.iter().filter(|s| s.error.is_none()).count();
+        writeln!(f, "{} of {} steps passed", passed, self.steps.len())
+    }
+}
+
+// Runner runs the steps of a scenario in the process attached to the current thread.
+#[derive(Default)]
+struct Runner {
+    contract: Option<Address>,
+    sender: Address,
+    plugins: HashMap<Address, Plugin>,
+    last: Option<InvokeResult<Vec<u8>>>,
+}
+
+impl Runner {
+    fn run(&mut self, step: &Step) -> Result<()> {
+        match step {
+            Step::Deploy { address, plugin } => {
+                match plugin {
+                    Some(path) => {
+                        self.plugins.insert(*address, Plugin::load(path)?);
+                    }
+                    None => deploy_crate_contract(*address)?,
+                }
+                self.contract = Some(*address);
+                Ok(())
+            }
+            Step::Call {
+                contract,
+                sender,
+                entry,
+                args,
+                expect_failure,
+            } => {
+                let contract = self.contract(contract)?;
+                if let Some(sender) = sender {
+                    self.sender = *sender;
+                }
+                if let Some(plugin) = self.plugins.get(&contract) {
+                    if let Some(f) = plugin.function(entry) {
+                        register_contract_function(contract, entry.clone(), f);
+                    }
+                }
+                self.last = None;
+                let args = args.iter().map(|a| a.bytes.clone()).collect();
+                let res = match catch_unwind(AssertUnwindSafe(|| {
+                    invoke::<Vec<u8>>(&self.sender, &contract, entry, args)
+                })) {
+                    Ok(Ok(res)) => res,
+                    // a call which is rejected, e.g. for invalid arguments, fails too
+                    Ok(Err(_)) if *expect_failure => return Ok(()),
+                    Ok(Err(e)) => return Err(e),
+                    Err(e) => {
+                        clear()?;
+                        return Err(error::from_str(format!("panicked: {}", panic_message(&*e))));
+                    }
+                };
+                let status = res.status;
+                self.last = Some(res);
+                match (status >= 0, expect_failure) {
+                    (false, false) => {
+                        Err(error::from_str(format!("failed with status {}", status)))
+                    }
+                    (true, true) => Err(error::from_str(
+                        "expected the call to fail, but it succeeded".to_string(),
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            Step::ExpectReturn(expected) => {
+                let last = self.last()?;
+                let actual = last.value.clone().unwrap_or_default();
+                match expected {
+                    Some(expected) if expected.bytes != actual => Err(error::from_str(format!(
+                        "expected {}, got {}",
+                        expected.text,
+                        expected.format(&actual)
+                    ))),
+                    None if !actual.is_empty() => Err(error::from_str(format!(
+                        "expected no return value, got {}",
+                        format_value("_", &actual)
+                    ))),
+                    _ => Ok(()),
+                }
+            }
+            Step::ExpectEvent { name, value } => {
+                let last = self.last()?;
+                let found = last
+                    .events
+                    .iter()
+                    .any(|e| e.name == *name && value.iter().all(|v| v.bytes == e.value));
+                if found {
+                    return Ok(());
+                }
+                let events: Vec<String> = last
+                    .events
+                    .iter()
+                    .map(|e| match value {
+                        Some(v) => format!("{} {}", e.name, v.format(&e.value)),
+                        None => format!("{} {}", e.name, format_value("_", &e.value)),
+                    })
+                    .collect();
+                Err(error::from_str(format!(
+                    "no such event in [{}]",
+                    events.join(", ")
+                )))
+            }
+            Step::ExpectState {
+                contract,
+                key,
+                value,
+            } => {
+                let contract = self.contract(contract)?;
+                let state = get_state()?;
+                let actual = state.get(&contract, &key.bytes);
+                match (value, actual) {
+                    (Some(expected), Some(actual)) if expected.bytes != actual => {
+                        Err(error::from_str(format!(
+                            "expected {}, got {}",
+                            expected.text,
+                            expected.format(actual)
+                        )))
+                    }
+                    (Some(expected), None) => Err(error::from_str(format!(
+                        "expected {}, got no value",
+                        expected.text
+                    ))),
+                    (None, Some(actual)) => Err(error::from_str(format!(
+                        "expected no value, got {}",
+                        format_value("_", actual)
+                    ))),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn contract(&self, contract: &Option<Address>) -> Result<Address> {
+        contract
+            .or(self.contract)
+            .ok_or_else(|| error::from_str("no contract is deployed".to_string()))
+    }
+
+    fn last(&self) -> Result<&InvokeResult<Vec<u8>>> {
+        self.last
+            .as_ref()
+            .ok_or_else(|| error::from_str("no call to check".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::ContractExport;
+    use hmcdk::api;
+
+    fn __scenario_mint() -> i32 {
+        let amount: u64 = api::get_arg(0).unwrap();
+        let total: u64 = api::read_state("total".as_bytes()).unwrap_or(0);
+        if total + amount > 1000 {
+            return -1;
+        }
+        api::write_state("total".as_bytes(), &(total + amount).to_bytes());
+        api::emit_event("minted", &amount.to_bytes()).unwrap();
+        api::return_value(&(total + amount).to_bytes())
+    }
+
+    inventory::submit! {
+        ContractExport {
+            name: "scenario_mint",
+            f: __scenario_mint,
+            signature: None,
+            module: module_path!(),
+        }
+    }
+
+    const SCENARIO: &str = r#"
+name: mint
+steps:
+  - deploy:
+      address: addr:0x00000000000000000000000000000000000000aa
+  - call:
+      sender: addr:0x0000000000000000000000000000000000000001
+      entry: scenario_mint
+      args: [u64:100]
+  - expect-return: u64:100
+  - expect-event: { name: minted, value: u64:100 }
+  - expect-state: { key: str:total, value: u64:100 }
+  - call: { entry: scenario_mint, args: [u64:1000], expect-failure: true }
+  - expect-state: { key: str:total, value: u64:99 }
+  - expect-state: { key: str:missing, value: null }
+  - call: { entry: scenario_mint, args: [u64:1] }
+  - expect-event: { name: burned }
+  - call: { entry: missing, expect-failure: true }
+  - call: { entry: missing }
+"#;
+
+    #[test]
+    fn typed_value_test() {
+        assert_eq!(5i64.to_bytes(), TypedValue::parse("i64:5").unwrap().bytes);
+        assert_eq!(vec![1u8], TypedValue::parse("bool:true").unwrap().bytes);
+        assert_eq!(
+            b"a:b c".to_vec(),
+            TypedValue::parse("str:a:b c").unwrap().bytes
+        );
+        assert_eq!(
+            vec![0xabu8, 0xcd],
+            TypedValue::parse("hex:0xabcd").unwrap().bytes
+        );
+        assert_eq!(
+            20,
+            TypedValue::parse("addr:0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed")
+                .unwrap()
+                .bytes
+                .len()
+        );
+        for invalid in &["5", "i64:x", "u8:256", "addr:0x01", "hex:0xabc", "f64:1.0"] {
+            TypedValue::parse(invalid).expect_err(invalid);
+        }
+    }
+
+    #[test]
+    fn scenario_test() {
+        let report = Scenario::from_yaml(SCENARIO).unwrap().run().unwrap();
+        assert!(!report.passed());
+        assert_eq!(
+            "scenario mint
+  ok   1. deploy 0x00000000000000000000000000000000000000AA
+  ok   2. call scenario_mint(u64:100)
+  ok   3. expect-return u64:100
+  ok   4. expect-event minted u64:100
+  ok   5. expect-state str:total = u64:100
+  ok   6. call scenario_mint(u64:1000)
+  FAIL 7. expect-state str:total = u64:99: expected u64:99, got u64:100
+  ok   8. expect-state str:missing = null
+  ok   9. call scenario_mint(u64:1)
+  FAIL 10. expect-event burned null: no such event in [minted 0x0000000000000001]
+  ok   11. call missing()
+  FAIL 12. call missing(): function not found: address=0x00000000000000000000000000000000000000AA entry=missing
+9 of 12 steps passed
+",
+            report.to_string()
+        );
+    }
+
+    #[test]
+    fn parse_test() {
+        let json =
+            r#"{"steps": [{"call": {"entry": "f", "args": ["i32:1"]}}, {"expect-return": null}]}"#;
+        assert_eq!(
+            Scenario {
+                name: String::new(),
+                steps: vec![
+                    Step::Call {
+                        contract: None,
+                        sender: None,
+                        entry: "f".to_string(),
+                        args: vec![TypedValue::parse("i32:1").unwrap()],
+                        expect_failure: false,
+                    },
+                    Step::ExpectReturn(None),
+                ],
+            },
+            Scenario::from_json(json).unwrap()
+        );
+        assert_eq!(
+            "step 2: unknown step `expect-revert`, expected one of deploy, call, expect-return, expect-event or expect-state",
+            Scenario::from_yaml("steps: [{deploy: {address: 'addr:0x00000000000000000000000000000000000000aa'}}, {expect-revert: null}]")
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "step 1: call: unknown field `args2`",
+            Scenario::from_yaml("steps: [{call: {entry: f, args2: []}}]")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
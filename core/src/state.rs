@@ -0,0 +1,148 @@
+use crate::address::to_checksum_hex;
+use crate::process::Result;
+use hmcdk::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// StateView is a read-only snapshot of the committed state of a process.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateView {
+    values: BTreeMap<(Address, Vec<u8>), Vec<u8>>,
+}
+
+impl StateView {
+    pub(crate) fn insert(&mut self, addr: Address, key: Vec<u8>, value: Vec<u8>) {
+        self.values.insert((addr, key), value);
+    }
+
+    pub fn get(&self, addr: &Address, key: &[u8]) -> Option<&[u8]> {
+        self.values
+            .get(&(*addr, key.to_vec()))
+            .map(|v| v.as_slice())
+    }
+
+    /// read decodes the value of key in the state of contract addr.
+    pub fn read<T: FromBytes>(&self, addr: &Address, key: &[u8]) -> Result<Option<T>> {
+        match self.get(addr, key) {
+            Some(v) => Ok(Some(T::from_bytes(v.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// iter returns all entries as (contract address, key, value) ordered by address and key.
+    pub fn iter(&self) -> impl Iterator<Item = (&Address, &[u8], &[u8])> {
+        self.values
+            .iter()
+            .map(|((addr, k), v)| (addr, k.as_slice(), v.as_slice()))
+    }
+
+    /// contract returns the entries of contract addr as (key, value) ordered by key.
+    pub fn contract<'a>(&'a self, addr: &Address) -> impl Iterator<Item = (&'a [u8], &'a [u8])> {
+        let addr = *addr;
+        self.iter()
+            .filter(move |(a, _, _)| **a == addr)
+            .map(|(_, k, v)| (k, v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// diff returns the entries which differ between self and next.
+    pub fn diff(&self, next: &StateView) -> Vec<StateChange> {
+        let mut changes = Vec::new();
+        for ((addr, key), v) in self.values.iter() {
+            match next.values.get(&(*addr, key.clone())) {
+                Some(nv) if nv == v => {}
+                nv => changes.push(StateChange {
+                    address: *addr,
+                    key: key.clone(),
+                    before: Some(v.clone()),
+                    after: nv.cloned(),
+                }),
+            }
+        }
+        for ((addr, key), nv) in next.values.iter() {
+            if !self.values.contains_key(&(*addr, key.clone())) {
+                changes.push(StateChange {
+                    address: *addr,
+                    key: key.clone(),
+                    before: None,
+                    after: Some(nv.clone()),
+                });
+            }
+        }
+        changes.sort_by(|a, b| (&a.address, &a.key).cmp(&(&b.address, &b.key)));
+        changes
+    }
+}
+
+/// StateChange is an entry which differs between two state views.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateChange {
+    pub address: Address,
+    pub key: Vec<u8>,
+    pub before: Option<Vec<u8>>,
+    pub after: Option<Vec<u8>>,
+}
+
+impl fmt::Display for StateChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = |v: &Option<Vec<u8>>| match v {
+            Some(v) => display_bytes(v),
+            None => "(none)".to_string(),
+        };
+        write!(
+            f,
+            "{} {}: {} -> {}",
+            to_checksum_hex(&self.address),
+            display_bytes(&self.key),
+            value(&self.before),
+            value(&self.after)
+        )
+    }
+}
+
+// display_bytes shows printable ASCII as a quoted string and anything else as hex, as keys are often text and values often binary.
+pub(crate) fn display_bytes(bs: &[u8]) -> String {
+    if !bs.is_empty() && bs.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        format!("{:?}", String::from_utf8_lossy(bs))
+    } else {
+        let hex: String = bs.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("0x{}", hex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmemu_codegen::address;
+
+    #[test]
+    fn diff_test() {
+        let a = address!("0x00000000000000000000000000000000000000aa");
+        let b = address!("0x00000000000000000000000000000000000000bb");
+        let mut before = StateView::default();
+        before.insert(a, b"same".to_vec(), vec![1]);
+        before.insert(a, b"changed".to_vec(), vec![1]);
+        before.insert(b, b"removed".to_vec(), vec![1]);
+        let mut after = before.clone();
+        after.insert(a, b"changed".to_vec(), vec![2]);
+        after.values.remove(&(b, b"removed".to_vec()));
+        after.insert(b, b"added".to_vec(), b"value".to_vec());
+
+        let changes: Vec<String> = before.diff(&after).iter().map(|c| c.to_string()).collect();
+        let (a, b) = (to_checksum_hex(&a), to_checksum_hex(&b));
+        let expected = vec![
+            format!("{} \"changed\": 0x01 -> 0x02", a),
+            format!("{} \"added\": (none) -> \"value\"", b),
+            format!("{} \"removed\": 0x01 -> (none)", b),
+        ];
+        assert_eq!(expected, changes);
+        assert!(after.diff(&after).is_empty());
+    }
+}
@@ -0,0 +1,376 @@
+//! Model-based testing of transaction sequences, enabled by the `proptest` feature.
+//!
+//! A `StateMachineTest` describes a contract by a reference model and the transactions it accepts.
+//! The harness sends random sequences of transactions to the contract, checks every outcome against the model,
+//! and shrinks a failing sequence to a minimal one.
+//!
+//! ```ignore
+//! struct Counter;
+//!
+//! impl StateMachineTest for Counter {
+//!     type Model = u32;
+//!
+//!     fn contract() -> Address {
+//!         COUNTER
+//!     }
+//!
+//!     fn setup() -> hmemu::Result<()> {
+//!         hmemu::register_contract_function(COUNTER, "inc".to_string(), contract_fn!(inc));
+//!         Ok(())
+//!     }
+//!
+//!     fn init_model() -> u32 {
+//!         0
+//!     }
+//!
+//!     fn transactions() -> Vec<Transaction> {
+//!         vec![Transaction::new("inc").args(hp::args((0u32..10,)))]
+//!     }
+//!
+//!     fn apply(model: &mut u32, call: &Call) -> Expected {
+//!         *model += call.arg::<u32>(0).unwrap();
+//!         Expected::value(*model)
+//!     }
+//! }
+//!
+//! Counter::run(Config::default()).unwrap();
+//! ```
+use crate::address::to_checksum_hex;
+use crate::process::{init_contract_address, invoke, Result};
+use crate::proptest::Runner;
+use crate::types::Args;
+use ::proptest::collection;
+use ::proptest::prelude::*;
+use ::proptest::strategy::Union;
+use ::proptest::test_runner::Config;
+use hmcdk::error;
+use hmcdk::prelude::*;
+use std::fmt;
+
+/// Transaction describes a kind of transaction: the entry it calls, and how its arguments and sender are generated.
+pub struct Transaction {
+    entry: String,
+    args: BoxedStrategy<Args>,
+    sender: BoxedStrategy<Address>,
+}
+
+impl Transaction {
+    /// new returns a transaction calling entry without arguments from arbitrary senders.
+    pub fn new(entry: &str) -> Self {
+        Transaction {
+            entry: entry.to_string(),
+            args: Just(Args::new()).boxed(),
+            sender: any::<[u8; 20]>().boxed(),
+        }
+    }
+
+    pub fn args<S: Strategy<Value = Args> + 'static>(mut self, args: S) -> Self {
+        self.args = args.boxed();
+        self
+    }
+
+    pub fn sender<S: Strategy<Value = Address> + 'static>(mut self, sender: S) -> Self {
+        self.sender = sender.boxed();
+        self
+    }
+
+    fn strategy(self) -> BoxedStrategy<Call> {
+        let entry = self.entry;
+        (self.sender, self.args)
+            .prop_map(move |(sender, args)| Call {
+                entry: entry.clone(),
+                sender,
+                args,
+            })
+            .boxed()
+    }
+}
+
+/// Call is a generated transaction.
+#[derive(Clone)]
+pub struct Call {
+    pub entry: String,
+    pub sender: Address,
+    pub args: Args,
+}
+
+impl Call {
+    /// arg decodes the argument at idx.
+    pub fn arg<T: FromBytes>(&self, idx: usize) -> Result<T> {
+        match self.args.get(idx) {
+            Some(arg) => T::from_bytes(arg.clone()),
+            None => Err(error::from_str(format!("argument {} not found", idx))),
+        }
+    }
+}
+
+impl fmt::Debug for Call {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| arg.iter().map(|b| format!("{:02x}", b)).collect())
+            .collect();
+        write!(
+            f,
+            "{}(sender={}, args=[{}])",
+            self.entry,
+            to_checksum_hex(&self.sender),
+            args.join(", ")
+        )
+    }
+}
+
+/// Expected is the outcome of a transaction predicted by the model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    /// The transaction succeeds and returns the value, or nothing.
+    Success(Option<Vec<u8>>),
+    /// The transaction fails.
+    Failure,
+    /// The outcome isn't checked.
+    Any,
+}
+
+impl Expected {
+    pub fn value<T: ToBytes>(v: T) -> Self {
+        Expected::Success(Some(v.to_bytes()))
+    }
+}
+
+pub trait StateMachineTest {
+    /// Model is the reference model of the contract state.
+    type Model;
+
+    /// MAX_TRANSACTIONS is the maximum length of the generated sequences.
+    const MAX_TRANSACTIONS: usize = 16;
+
+    /// contract returns the address the transactions are sent to.
+    fn contract() -> Address;
+
+    /// setup prepares the process every sequence starts from, e.g. registers the contract functions.
+    /// Only committed state is carried over to the sequences.
+    fn setup() -> Result<()> {
+        Ok(())
+    }
+
+    fn init_model() -> Self::Model;
+
+    fn transactions() -> Vec<Transaction>;
+
+    /// apply applies the call to the model, and returns the outcome the contract must produce.
+    fn apply(model: &mut Self::Model, call: &Call) -> Expected;
+
+    /// check compares the contract state with the model after each transaction.
+    /// It runs in the process of the sequence, so the contract state can be read by `hmcdk::api::read_state`.
+    fn check(_model: &Self::Model) -> Result<()> {
+        Ok(())
+    }
+
+    /// run tests random sequences of transactions, and reports the minimal failing sequence if any.
+    fn run(config: Config) -> Result<()>
+    where
+        Self: Sized + 'static,
+    {
+        let txs: Vec<_> = Self::transactions()
+            .into_iter()
+            .map(Transaction::strategy)
+            .collect();
+        if txs.is_empty() {
+            return Err(error::from_str("no transactions are defined".to_string()));
+        }
+        let calls = collection::vec(Union::new(txs), 1..=Self::MAX_TRANSACTIONS);
+        Runner::new(config)
+            .setup(Self::setup)
+            .run(&calls, |calls| run_calls::<Self>(&calls))
+    }
+}
+
+fn run_calls<M: StateMachineTest>(calls: &[Call]) -> Result<()> {
+    let mut model = M::init_model();
+    for (i, call) in calls.iter().enumerate() {
+        let expected = M::apply(&mut model, call);
+        let res = invoke::<Vec<u8>>(&call.sender, &M::contract(), &call.entry, call.args.clone())?;
+        let matched = match &expected {
+            Expected::Success(value) => res.is_success() && &res.value == value,
+            Expected::Failure => !res.is_success(),
+            Expected::Any => true,
+        };
+        if !matched {
+            return Err(error::from_str(format!(
+                "transaction {} {:?}: expected {:?}, but got status={} value={:?}",
+                i, call, expected, res.status, res.value
+            )));
+        }
+        // invoke restores the contract address, so point the process at the contract again for check
+        init_contract_address(&M::contract())?;
+        if let Err(e) = M::check(&model) {
+            return Err(error::from_str(format!(
+                "transaction {} {:?}: {}",
+                i, call, e
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proptest as hp;
+    use hmcdk::api;
+    use hmemu_codegen::address;
+
+    const COUNTER: Address = address!("0x00000000000000000000000000000000000000cc");
+
+    fn count() -> u32 {
+        api::read_state("count".as_bytes()).unwrap_or(0)
+    }
+
+    fn inc() -> i32 {
+        let n: u32 = api::get_arg(0).unwrap();
+        let c = count() + n;
+        api::write_state("count".as_bytes(), &c.to_bytes());
+        api::return_value(&c.to_bytes())
+    }
+
+    fn dec() -> i32 {
+        let c = count();
+        if c == 0 {
+            return -1;
+        }
+        api::write_state("count".as_bytes(), &(c - 1).to_bytes());
+        api::return_value(&(c - 1).to_bytes())
+    }
+
+    // buggy_dec forgets to decrement once the count reaches 5.
+    fn buggy_dec() -> i32 {
+        let c = count();
+        if c == 0 {
+            return -1;
+        }
+        let c = if c >= 5 { c } else { c - 1 };
+        api::write_state("count".as_bytes(), &c.to_bytes());
+        api::return_value(&c.to_bytes())
+    }
+
+    fn apply_counter(model: &mut u32, call: &Call) -> Expected {
+        match call.entry.as_str() {
+            "inc" => {
+                *model += call.arg::<u32>(0).unwrap();
+                Expected::value(*model)
+            }
+            "dec" if *model == 0 => Expected::Failure,
+            "dec" => {
+                *model -= 1;
+                Expected::value(*model)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn counter_transactions() -> Vec<Transaction> {
+        vec![
+            Transaction::new("inc").args(hp::args((0u32..3,))),
+            Transaction::new("dec"),
+        ]
+    }
+
+    struct Counter;
+
+    impl StateMachineTest for Counter {
+        type Model = u32;
+
+        fn contract() -> Address {
+            COUNTER
+        }
+
+        fn setup() -> Result<()> {
+            crate::register_contract_function(COUNTER, "inc".to_string(), inc);
+            crate::register_contract_function(COUNTER, "dec".to_string(), dec);
+            Ok(())
+        }
+
+        fn init_model() -> u32 {
+            0
+        }
+
+        fn transactions() -> Vec<Transaction> {
+            counter_transactions()
+        }
+
+        fn apply(model: &mut u32, call: &Call) -> Expected {
+            apply_counter(model, call)
+        }
+
+        fn check(model: &u32) -> Result<()> {
+            if count() != *model {
+                return Err(error::from_str(format!(
+                    "count is {}, but the model is {}",
+                    count(),
+                    model
+                )));
+            }
+            Ok(())
+        }
+    }
+
+    struct BuggyCounter;
+
+    impl StateMachineTest for BuggyCounter {
+        type Model = u32;
+
+        fn contract() -> Address {
+            COUNTER
+        }
+
+        fn setup() -> Result<()> {
+            crate::register_contract_function(COUNTER, "inc".to_string(), inc);
+            crate::register_contract_function(COUNTER, "dec".to_string(), buggy_dec);
+            Ok(())
+        }
+
+        fn init_model() -> u32 {
+            0
+        }
+
+        fn transactions() -> Vec<Transaction> {
+            counter_transactions()
+        }
+
+        fn apply(model: &mut u32, call: &Call) -> Expected {
+            apply_counter(model, call)
+        }
+    }
+
+    #[test]
+    fn state_machine_test() {
+        Counter::run(Config::with_cases(64)).unwrap();
+    }
+
+    #[test]
+    fn shrink_sequence_test() {
+        let err = BuggyCounter::run(Config::with_cases(256)).expect_err("expect error");
+        let msg = err.to_string();
+        let minimal = msg
+            .split("minimal failing input: ")
+            .nth(1)
+            .and_then(|s| s.lines().next())
+            .expect("expect minimal input");
+        // at least three increments are needed to reach 5, and then the decrement fails
+        assert!(minimal.starts_with("[inc("), "{}", msg);
+        assert_eq!(1, minimal.matches("dec(").count(), "{}", msg);
+        assert!(minimal.matches("inc(").count() >= 3, "{}", msg);
+        // the trace shows the transactions of the minimal sequence
+        let trace = msg
+            .split("trace of the minimal failing input:\n")
+            .nth(1)
+            .expect("expect trace");
+        assert_eq!(
+            minimal.matches("inc(").count(),
+            trace.matches(".inc(").count(),
+            "{}",
+            msg
+        );
+    }
+}
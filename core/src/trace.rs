@@ -0,0 +1,325 @@
+//! Structured traces of the host calls made by contracts.
+//!
+//! ```ignore
+//! hmemu::set_trace(true)?;
+//! hmemu::invoke::<Vec<u8>>(&SENDER, &TOKEN, "transfer", args![to, 10u64])?;
+//! print!("{}", hmemu::trace::render_tree(&hmemu::take_trace()?));
+//! ```
+use crate::address::to_checksum_hex;
+use crate::process::Result;
+use crate::state::display_bytes;
+use hmcdk::error;
+use hmcdk::prelude::*;
+use std::path::Path;
+
+/// TraceEvent is a host call, or a contract call made through the emulator.
+///
+/// A contract call is recorded as a `call` event, whose arguments are the callee address, the entry and
+/// the arguments of the call, followed by the host calls of the callee and a `return` event with its status and return value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    /// timestamp is the time in nanoseconds since tracing was enabled.
+    pub timestamp: u64,
+    /// depth is the number of nested contract calls the event happened in.
+    pub depth: u32,
+    /// contract is the address of the contract which made the call.
+    pub contract: Address,
+    pub name: String,
+    pub args: Vec<Vec<u8>>,
+    /// result is the code returned by the host call, e.g. the number of bytes written to the buffer, or -1 on error.
+    pub result: i32,
+    /// output is the bytes the host call wrote back to the contract.
+    pub output: Vec<u8>,
+}
+
+impl TraceEvent {
+    /// to_json returns the event as a single-line JSON object, with bytes as 0x-prefixed hex strings.
+    pub fn to_json(&self) -> String {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|a| format!("\"{}\"", hex(a)))
+            .collect();
+        format!(
+            "{{\"timestamp\":{},\"depth\":{},\"contract\":\"{}\",\"name\":\"{}\",\"args\":[{}],\"result\":{},\"output\":\"{}\"}}",
+            self.timestamp,
+            self.depth,
+            to_checksum_hex(&self.contract),
+            escape_json(&self.name),
+            args.join(","),
+            self.result,
+            hex(&self.output)
+        )
+    }
+}
+
+/// render_tree renders events as lines indented by the contract calls they happen in.
+pub fn render_tree(events: &[TraceEvent]) -> String {
+    let mut s = String::new();
+    let mut level = 0;
+    for e in events.iter() {
+        if e.name == "return" {
+            level = std::cmp::max(level, 1) - 1;
+        }
+        s.push_str(&"  ".repeat(level));
+        s.push_str(&render_event(e));
+        s.push('\n');
+        if e.name == "call" {
+            level += 1;
+        }
+    }
+    s
+}
+
+fn render_event(e: &TraceEvent) -> String {
+    let args = |args: &[Vec<u8>]| {
+        args.iter()
+            .map(|a| display_bytes(a))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut s = match e.name.as_str() {
+        "call" if e.args.len() >= 2 => {
+            return format!(
+                "call {}.{}({})",
+                to_checksum_hex(&to_address(&e.args[0])),
+                String::from_utf8_lossy(&e.args[1]),
+                args(&e.args[2..])
+            );
+        }
+        "return" => format!("return {}", e.result),
+        // the writes committed to a contract, prefixed by its address
+        "commit_state" if !e.args.is_empty() => format!(
+            "commit_state({}, {}) -> {}",
+            to_checksum_hex(&to_address(&e.args[0])),
+            args(&e.args[1..]),
+            e.result
+        ),
+        _ => format!("{}({}) -> {}", e.name, args(&e.args), e.result),
+    };
+    if !e.output.is_empty() {
+        s.push(' ');
+        s.push_str(&display_bytes(&e.output));
+    }
+    s
+}
+
+/// render_json_lines renders events as JSON objects, one per line.
+pub fn render_json_lines(events: &[TraceEvent]) -> String {
+    events.iter().map(|e| e.to_json() + "\n").collect()
+}
+
+/// render_chrome_trace renders events in the Chrome trace event format, which chrome://tracing and Perfetto open.
+///
+/// Contract calls become nested duration slices named by their entry, and host calls become instant events
+/// categorized as `state`, `event`, `commit` or `host`. Call slices carry a `gas` arg, which is always 0
+/// since the emulator doesn't meter gas.
+pub fn render_chrome_trace(events: &[TraceEvent]) -> String {
+    let mut entries = Vec::new();
+    for e in events.iter() {
+        let ts = format!("{}.{:03}", e.timestamp / 1000, e.timestamp % 1000);
+        let entry = match e.name.as_str() {
+            "call" if e.args.len() >= 2 => {
+                let args: Vec<String> = e.args[2..].iter().map(|a| format!("\"{}\"", hex(a))).collect();
+                format!(
+                    "{{\"name\":\"{}\",\"cat\":\"call\",\"ph\":\"B\",\"ts\":{},\"pid\":0,\"tid\":0,\"args\":{{\"caller\":\"{}\",\"callee\":\"{}\",\"args\":[{}],\"gas\":0}}}}",
+                    escape_json(&String::from_utf8_lossy(&e.args[1])),
+                    ts,
+                    to_checksum_hex(&e.contract),
+                    to_checksum_hex(&to_address(&e.args[0])),
+                    args.join(",")
+                )
+            }
+            "return" => format!(
+                "{{\"ph\":\"E\",\"ts\":{},\"pid\":0,\"tid\":0,\"args\":{{\"status\":{},\"output\":\"{}\",\"gas\":0}}}}",
+                ts,
+                e.result,
+                hex(&e.output)
+            ),
+            name => {
+                let args: Vec<String> = e.args.iter().map(|a| format!("\"{}\"", hex(a))).collect();
+                let cat = match name {
+                    "read_state" | "write_state" => "state",
+                    "emit_event" => "event",
+                    "commit_state" => "commit",
+                    _ => "host",
+                };
+                format!(
+                    "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"i\",\"s\":\"t\",\"ts\":{},\"pid\":0,\"tid\":0,\"args\":{{\"contract\":\"{}\",\"depth\":{},\"args\":[{}],\"result\":{},\"output\":\"{}\"}}}}",
+                    escape_json(name),
+                    cat,
+                    ts,
+                    to_checksum_hex(&e.contract),
+                    e.depth,
+                    args.join(","),
+                    e.result,
+                    hex(&e.output)
+                )
+            }
+        };
+        entries.push(entry);
+    }
+    format!("{{\"traceEvents\":[\n{}\n]}}\n", entries.join(",\n"))
+}
+
+/// write_chrome_trace writes events to path in the Chrome trace event format.
+pub fn write_chrome_trace<P: AsRef<Path>>(path: P, events: &[TraceEvent]) -> Result<()> {
+    std::fs::write(path.as_ref(), render_chrome_trace(events)).map_err(|e| {
+        error::from_str(format!(
+            "failed to write {}: {}",
+            path.as_ref().display(),
+            e
+        ))
+    })
+}
+
+fn to_address(bs: &[u8]) -> Address {
+    let mut addr: Address = Default::default();
+    let n = std::cmp::min(addr.len(), bs.len());
+    addr[..n].copy_from_slice(&bs[..n]);
+    addr
+}
+
+pub(crate) fn hex(bs: &[u8]) -> String {
+    let hex: String = bs.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("0x{}", hex)
+}
+
+pub(crate) fn escape_json(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::*;
+    use hmcdk::api;
+    use hmemu_codegen::address;
+
+    const CONTRACT_A: Address = address!("0x00000000000000000000000000000000000000aa");
+    const CONTRACT_B: Address = address!("0x00000000000000000000000000000000000000bb");
+
+    fn func_a() -> i32 {
+        let n: u32 = api::get_arg(0).unwrap();
+        api::write_state("n".as_bytes(), &n.to_bytes());
+        let res: Vec<u8> = api::call_contract(&CONTRACT_B, "func_b".as_bytes(), vec![]).unwrap();
+        api::return_value(&res)
+    }
+
+    fn func_b() -> i32 {
+        api::emit_event("ping", "b".as_bytes()).unwrap();
+        api::return_value("ok".as_bytes())
+    }
+
+    #[test]
+    fn trace_test() {
+        let sender = address!("0x0000000000000000000000000000000000000001");
+        let events = run_process(|| {
+            register_contract_function(CONTRACT_A, "func_a".to_string(), func_a);
+            register_contract_function(CONTRACT_B, "func_b".to_string(), func_b);
+            set_trace(true)?;
+            invoke::<Vec<u8>>(&sender, &CONTRACT_A, "func_a", crate::args![7u32])?;
+            let events = take_trace()?;
+            assert!(take_trace()?.is_empty());
+            Ok(events)
+        })
+        .unwrap();
+
+        let (a, b) = (to_checksum_hex(&CONTRACT_A), to_checksum_hex(&CONTRACT_B));
+        let expected = vec![
+            format!("call {}.func_a(0x00000007)", a),
+            "  get_arg(0x00000000) -> 4 0x00000007".to_string(),
+            "  write_state(\"n\", 0x00000007) -> 0".to_string(),
+            format!("  call {}.func_b()", b),
+            "    emit_event(\"ping\", \"b\") -> 0".to_string(),
+            "    set_response(\"ok\") -> 0".to_string(),
+            "  return 0 \"ok\"".to_string(),
+            "  set_response(\"ok\") -> 0".to_string(),
+            "return 0 \"ok\"".to_string(),
+            format!("commit_state({}, \"n\", 0x00000007) -> 0", a),
+        ];
+        assert_eq!(expected.join("\n") + "\n", render_tree(&events));
+
+        let depths: Vec<u32> = events.iter().map(|e| e.depth).collect();
+        assert_eq!(vec![0, 0, 0, 0, 1, 1, 0, 0, 0, 0], depths);
+        assert!(events.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert_eq!(CONTRACT_B, events[4].contract);
+
+        let lines = render_json_lines(&events[1..2]);
+        assert_eq!(
+            format!(
+                "{{\"timestamp\":{},\"depth\":0,\"contract\":\"{}\",\"name\":\"get_arg\",\"args\":[\"0x00000000\"],\"result\":4,\"output\":\"0x00000007\"}}\n",
+                events[1].timestamp, a
+            ),
+            lines
+        );
+    }
+
+    #[test]
+    fn chrome_trace_test() {
+        let sender = address!("0x0000000000000000000000000000000000000001");
+        let events = run_process(|| {
+            register_contract_function(CONTRACT_A, "func_a".to_string(), func_a);
+            register_contract_function(CONTRACT_B, "func_b".to_string(), func_b);
+            set_trace(true)?;
+            invoke::<Vec<u8>>(&sender, &CONTRACT_A, "func_a", crate::args![7u32])?;
+            take_trace()
+        })
+        .unwrap();
+
+        let trace = render_chrome_trace(&events);
+        assert!(trace.starts_with("{\"traceEvents\":[\n"));
+        assert!(trace.ends_with("\n]}\n"));
+        let lines: Vec<&str> = trace.lines().collect();
+        // the slices of the calls are nested
+        let phases: Vec<&str> = lines
+            .iter()
+            .filter_map(|l| l.split("\"ph\":\"").nth(1))
+            .map(|l| &l[..1])
+            .collect();
+        assert_eq!(
+            vec!["B", "i", "i", "B", "i", "i", "E", "i", "E", "i"],
+            phases
+        );
+        assert!(lines[4].starts_with(&format!(
+            "{{\"name\":\"func_b\",\"cat\":\"call\",\"ph\":\"B\",\"ts\":{}.{:03},\"pid\":0,\"tid\":0,\"args\":{{\"caller\":\"{}\",\"callee\":\"{}\",\"args\":[],\"gas\":0}}}},",
+            events[3].timestamp / 1000,
+            events[3].timestamp % 1000,
+            to_checksum_hex(&CONTRACT_A),
+            to_checksum_hex(&CONTRACT_B)
+        )));
+        assert!(lines[5].contains("\"name\":\"emit_event\",\"cat\":\"event\""));
+        assert!(lines[7].contains("\"args\":{\"status\":0,\"output\":\"0x6f6b\",\"gas\":0}"));
+
+        let path = std::env::temp_dir().join(format!("hmemu-trace-{}.json", std::process::id()));
+        write_chrome_trace(&path, &events).unwrap();
+        assert_eq!(trace, std::fs::read_to_string(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn disabled_trace_test() {
+        run_process(|| {
+            init_contract_address(&CONTRACT_B)?;
+            func_b();
+            assert!(take_trace()?.is_empty());
+            set_trace(true)?;
+            func_b();
+            assert_eq!(2, take_trace()?.len());
+            set_trace(false)?;
+            func_b();
+            assert!(take_trace()?.is_empty());
+            Ok(())
+        })
+        .unwrap();
+    }
+}
@@ -23,7 +23,64 @@ impl ArgsBuilder {
         self.values.push(arg.to_vec());
     }
 
+    pub fn arg<T: ToBytes>(mut self, arg: T) -> Self {
+        self.push(arg);
+        self
+    }
+
+    pub fn arg_bytes(mut self, arg: &[u8]) -> Self {
+        self.push_bytes(arg);
+        self
+    }
+
     pub fn convert_to_vec(self) -> Vec<Vec<u8>> {
         self.values
     }
 }
+
+impl From<ArgsBuilder> for Args {
+    fn from(builder: ArgsBuilder) -> Self {
+        builder.values
+    }
+}
+
+/// args builds `Args` from a list of values implementing `ToBytes`.
+///
+/// ```ignore
+/// let args = args![1i64, CONTRACT_B, "name"];
+/// ```
+#[macro_export]
+macro_rules! args {
+    ($($arg:expr),* $(,)?) => {
+        $crate::types::Args::from($crate::types::ArgsBuilder::new()$(.arg($arg))*)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmemu_codegen::address;
+
+    #[test]
+    fn args_test() {
+        const CONTRACT: Address = address!("0x00000000000000000000000000000000000000bb");
+        let mut builder = ArgsBuilder::new();
+        builder.push(1i64);
+        builder.push(CONTRACT);
+        builder.push_bytes("name".as_bytes());
+        let expected = builder.convert_to_vec();
+
+        assert_eq!(expected, crate::args![1i64, CONTRACT, "name"]);
+        assert_eq!(expected, crate::args![1i64, CONTRACT, "name",]);
+        assert_eq!(
+            expected,
+            Args::from(
+                ArgsBuilder::new()
+                    .arg(1i64)
+                    .arg(CONTRACT)
+                    .arg_bytes("name".as_bytes())
+            )
+        );
+        assert!(crate::args![].is_empty());
+    }
+}
@@ -7,6 +7,7 @@ pub fn init() -> R<i32> {
     Ok(None)
 }
 
+#[hmemu::export]
 #[contract]
 pub fn contract_func() -> R<i64> {
     let x: i64 = api::get_arg(0)?;
@@ -23,20 +24,83 @@ pub fn contract_func() -> R<i64> {
 mod othermod {
     use super::*;
 
+    #[hmemu::export]
     #[contract]
     pub fn cfn() -> R<String> {
         Ok(Some("ok".to_string()))
     }
 }
 
+#[hmemu::abi]
+pub mod token {
+    use super::*;
+
+    fn balance_key(addr: &Address) -> Vec<u8> {
+        [b"balance/", &addr[..]].concat()
+    }
+
+    #[contract]
+    pub fn mint() -> R<u64> {
+        let sender = api::get_sender()?;
+        let amount: u64 = api::get_arg(0)?;
+        let balance = api::read_state(&balance_key(&sender)).unwrap_or(0) + amount;
+        api::write_state(&balance_key(&sender), &balance.to_bytes());
+        Ok(Some(balance))
+    }
+
+    #[contract]
+    pub fn transfer() -> R<u64> {
+        let sender = api::get_sender()?;
+        let to: Address = api::get_arg(0)?;
+        let amount: u64 = api::get_arg(1)?;
+        let from_balance: u64 = api::read_state(&balance_key(&sender)).unwrap_or(0);
+        if from_balance < amount {
+            return Err(hmcdk::error::from_str("insufficient balance"));
+        }
+        let to_balance: u64 = api::read_state(&balance_key(&to)).unwrap_or(0);
+        api::write_state(&balance_key(&sender), &(from_balance - amount).to_bytes());
+        api::write_state(&balance_key(&to), &(to_balance + amount).to_bytes());
+        api::emit_event("transfer", &amount.to_bytes())?;
+        Ok(Some(from_balance - amount))
+    }
+
+    #[contract]
+    pub fn balance_of() -> R<u64> {
+        let owner = api::get_arg::<Address>(0)?;
+        Ok(Some(api::read_state(&balance_key(&owner)).unwrap_or(0)))
+    }
+}
+
+#[hmemu::abi]
+pub mod memo {
+    use super::*;
+
+    fn read_memo() -> Result<String, Error> {
+        api::get_arg(1)
+    }
+
+    #[contract]
+    pub fn post() -> R<String> {
+        let id: u64 = api::get_arg(0)?;
+        let memo = read_memo()?;
+        api::write_state(&id.to_bytes(), memo.as_bytes());
+        Ok(Some(memo))
+    }
+
+    #[contract]
+    pub fn tip() -> R<u64> {
+        let amount: u64 = api::get_arg(0)?;
+        let bonus: u64 = api::get_arg(1).unwrap_or(0);
+        Ok(Some(amount + bonus))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate hmemu;
     use super::*;
-    use hmcdk::error;
-    use hmcdk::utils;
-    use hmemu::contract_fn;
     use hmemu::types::ArgsBuilder;
+    use hmemu::{address, args, contract_fn};
 
     #[test]
     fn simple_process_execution() {
@@ -77,26 +141,47 @@ mod tests {
         .unwrap();
     }
 
-    #[contract]
-    fn external_func() -> R<i32> {
-        Ok(Some(100))
+    #[hmemu::test(
+        sender = "0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c",
+        args = [1i64, 2i64]
+    )]
+    fn contract_func_attr_test() {
+        assert_eq!(Some(3), contract_func()?);
+        assert_eq!(
+            address!("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c"),
+            api::get_sender()?
+        );
     }
 
-    fn hex_to_address(hex_str: &str) -> Result<Address, error::Error> {
-        let b = utils::hex_to_bytes(hex_str);
-        let mut addr: Address = Default::default();
-        addr.copy_from_slice(&b);
-        if addr.len() == 20 {
-            Ok(addr)
-        } else {
-            Err(error::from_str("invalid length"))
+    // a `#[test]` written along with `#[hmemu::test]` doesn't make the test run twice
+    #[hmemu::test(args = [1i64, 2i64])]
+    #[test]
+    fn contract_func_return_test() {
+        let ret = contract_func()?;
+        if ret == Some(3) {
+            return Ok(());
         }
+        assert_eq!(Some(3), ret);
+    }
+
+    #[hmemu::test(contract = "0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a")]
+    fn contract_address_attr_test() -> hmemu::Result<()> {
+        assert_eq!(
+            address!("0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a"),
+            api::get_contract_address()?
+        );
+        Ok(())
+    }
+
+    #[contract]
+    fn external_func() -> R<i32> {
+        Ok(Some(100))
     }
 
     #[test]
     fn call_test() {
-        let sender = hex_to_address("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c").unwrap();
-        let contract = hex_to_address("0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a").unwrap();
+        let sender = address!("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c");
+        let contract = address!("0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a");
         hmemu::run_process(|| {
             hmemu::register_contract_function(
                 contract,
@@ -117,7 +202,7 @@ mod tests {
 
     #[test]
     fn lookup_contract_fn_test() {
-        let sender = hex_to_address("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c").unwrap();
+        let sender = address!("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c");
         hmemu::run_process(|| {
             hmemu::call_contract(&sender, ArgsBuilder::new().convert_to_vec(), || {
                 assert_eq!(contract_fn!(othermod::cfn)(), 0);
@@ -127,4 +212,145 @@ mod tests {
         })
         .unwrap();
     }
+
+    #[test]
+    fn contract_fn_path_test() {
+        let sender = address!("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c");
+        hmemu::run_process(|| {
+            hmemu::call_contract(&sender, args![], || {
+                assert_eq!(contract_fn!(self::external_func)(), 0);
+                assert_eq!(contract_fn!(super::othermod::cfn)(), 0);
+                assert_eq!(contract_fn!(crate::othermod::cfn)(), 0);
+                assert_eq!(contract_fn!(crate::init)(), 0);
+                Ok(())
+            })?;
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn abi_test() {
+        let fns: Vec<String> = token::ABI.functions.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            vec![
+                "mint(u64) -> u64",
+                "transfer(Address, u64) -> u64",
+                "balance_of(Address) -> u64"
+            ],
+            fns
+        );
+        assert_eq!(token::ABI.to_json(), token::ABI_JSON);
+
+        // an argument read by a helper or an optional one makes the arity open
+        let fns: Vec<String> = memo::ABI.functions.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            vec!["post(u64, ..) -> String", "tip(u64, u64, ..) -> u64"],
+            fns
+        );
+    }
+
+    #[test]
+    fn helper_arg_test() {
+        let sender = address!("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c");
+        hmemu::run_process(|| {
+            let client =
+                memo::MemoClient::deploy(address!("0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a"));
+            assert_eq!(
+                Some("hello".to_string()),
+                hmemu::invoke_value::<String>(
+                    &sender,
+                    &client.address,
+                    "post",
+                    args![1u64, "hello"]
+                )?
+            );
+            assert_eq!(Some(7), client.tip(&sender, 5, 2)?);
+            assert_eq!(
+                Some(5),
+                hmemu::invoke_value::<u64>(&sender, &client.address, "tip", args![5u64])?
+            );
+            let err = hmemu::invoke::<u64>(&sender, &client.address, "tip", args![])
+                .expect_err("expect invalid argument");
+            assert!(err
+                .to_string()
+                .starts_with("tip(u64, u64, ..): expected at least 1 arguments, got 0"));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn client_test() {
+        let alice = address!("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c");
+        let bob = address!("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+        hmemu::run_process(|| {
+            let client =
+                token::TokenClient::deploy(address!("0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a"));
+            assert_eq!(Some(100), client.mint(&alice, 100)?);
+            assert_eq!(Some(70), client.transfer(&alice, bob, 30)?);
+            assert_eq!(Some(30), client.balance_of(&alice, bob)?);
+            client
+                .transfer(&bob, alice, 31)
+                .expect_err("expect insufficient balance");
+
+            // the arguments are validated against the signatures in the ABI
+            let err = hmemu::invoke::<u64>(&alice, &client.address, "transfer", args![bob, 30i32])
+                .expect_err("expect invalid argument");
+            assert!(err
+                .to_string()
+                .starts_with("transfer(Address, u64): argument 1 (u64) must be 8 bytes, got 4"));
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn deploy_crate_contract_test() {
+        let sender = address!("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c");
+        let contract = address!("0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a");
+        hmemu::run_process(|| {
+            hmemu::deploy_crate_contract(contract)?;
+            assert_eq!(
+                Some(3),
+                hmemu::invoke_value::<i64>(&sender, &contract, "contract_func", args![1i64, 2i64])?
+            );
+            assert_eq!(
+                Some("ok".to_string()),
+                hmemu::invoke_value::<String>(&sender, &contract, "cfn", args![])?
+            );
+            assert_eq!(
+                Some(10),
+                hmemu::invoke_value::<u64>(&sender, &contract, "mint", args![10u64])?
+            );
+            hmemu::invoke::<i64>(&sender, &contract, "contract_func", args![1i64])
+                .expect_err("expect invalid argument");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn deploy_module_contract_test() {
+        let sender = address!("0x1221a0726d56aedea9dbe2522ddae3dd8ed0f36c");
+        let contract = address!("0xd8eba1f372b9e0d378259f150d52c2e6c2e4109a");
+        hmemu::run_process(|| {
+            hmemu::deploy_module_contract(contract, "first::memo")?;
+            assert_eq!(
+                Some(5),
+                hmemu::invoke_value::<u64>(&sender, &contract, "tip", args![5u64])?
+            );
+            hmemu::invoke::<u64>(&sender, &contract, "mint", args![10u64])
+                .expect_err("expect function not found");
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn scenario_test() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios/token.yaml");
+        let report = hmemu::run_scenario(path).unwrap();
+        assert!(report.passed(), "{}", report);
+    }
 }
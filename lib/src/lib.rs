@@ -1,4 +1,8 @@
 extern crate core;
+pub use core::abi;
+pub use core::accounts;
+pub use core::address;
+pub use core::args;
 pub use core::process;
 pub use core::types;
 pub use core::*;
@@ -6,13 +10,16 @@ pub use core::*;
 extern crate hmemu_codegen;
 pub use hmemu_codegen::*;
 
-#[macro_export(local_inner_macros)]
+/// contract_fn returns the contract API exported by a `#[contract]` function as a `fn() -> i32`,
+/// which can be registered by `register_contract_function`.
+/// The path may be relative, or start with `crate::`, `super::` or `self::`.
+///
+/// ```ignore
+/// hmemu::register_contract_function(TOKEN, "transfer".to_string(), contract_fn!(token::transfer));
+/// ```
+#[macro_export]
 macro_rules! contract_fn {
-    ($($t:tt)*) => {{
-        struct Wrap;
-        impl Wrap {
-            lookup_contract_fn_impl!($($t)*);
-        }
-        Wrap::output()
-    }}
+    ($($t:tt)*) => {
+        $crate::lookup_contract_fn_impl!($($t)*)
+    };
 }
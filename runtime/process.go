@@ -1,12 +1,22 @@
 package main
 
+/*
+#include <pthread.h>
+
+static unsigned long long current_thread_id() {
+	return (unsigned long long)pthread_self();
+}
+*/
 import "C"
 import (
+	"container/list"
+	"encoding/binary"
 	"errors"
 	"fmt"
+	"sort"
 	"sync"
+	"time"
 	"unsafe"
-	"container/list"
 
 	"github.com/bluele/hypermint/pkg/abci/store"
 	sdk "github.com/bluele/hypermint/pkg/abci/types"
@@ -22,62 +32,202 @@ var (
 	defaultLogger = logger.GetDefaultLogger("*:debug").With("module", "process")
 )
 
+// ProcessManager manages processes, each of which is attached to at most one OS thread.
+// Host calls resolve their process from the calling thread, so processes on different threads run concurrently.
+//
+// Slots of destroyed processes are reused. A pid carries the generation of its slot,
+// so a stale pid of a destroyed process never refers to the process reusing the slot.
 type ProcessManager struct {
-	mu   sync.Mutex
-	cpid int
-	pss  []*Process
+	mu      sync.Mutex
+	slots   []slot
+	free    []int
+	threads map[uint64]int
+	// errors has the last error of each thread, which __get_last_error returns
+	errors map[uint64]string
+}
+
+type slot struct {
+	generation int
+	ps         *Process
+}
+
+const (
+	pidIndexBits      = 16
+	pidIndexMask      = (1 << pidIndexBits) - 1
+	pidGenerationMask = (1 << (31 - pidIndexBits)) - 1
+)
+
+func makePID(index, generation int) int {
+	return generation<<pidIndexBits | index
+}
+
+func splitPID(pid int) (int, int) {
+	return pid & pidIndexMask, pid >> pidIndexBits
 }
 
-func (pm *ProcessManager) GetMutex(pid int) {
+func currentThreadID() uint64 {
+	return uint64(C.current_thread_id())
+}
+
+func (pm *ProcessManager) attach(pid int) {
+	if pm.threads == nil {
+		pm.threads = make(map[uint64]int)
+	}
+	pm.threads[currentThreadID()] = pid
+}
+
+func (pm *ProcessManager) lookup(pid int) (*Process, error) {
+	index, generation := splitPID(pid)
+	if pid < 0 || len(pm.slots) <= index {
+		return nil, fmt.Errorf("not found pid %v", pid)
+	}
+	s := pm.slots[index]
+	if s.ps == nil || s.generation != generation {
+		return nil, fmt.Errorf("stale pid %v", pid)
+	}
+	return s.ps, nil
+}
+
+func (pm *ProcessManager) Attach(pid int) error {
 	pm.mu.Lock()
-	if pid >= 0 {
-		pm.cpid = pid
+	defer pm.mu.Unlock()
+	if _, err := pm.lookup(pid); err != nil {
+		return err
+	}
+	tid := currentThreadID()
+	for t, p := range pm.threads {
+		if p == pid && t != tid {
+			return fmt.Errorf("pid %v is attached to another thread", pid)
+		}
 	}
+	pm.attach(pid)
+	return nil
 }
 
-func (pm *ProcessManager) ReleaseMutex() {
-	pm.mu.Unlock()
+func (pm *ProcessManager) Detach() {
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	delete(pm.threads, currentThreadID())
 }
 
 func (pm *ProcessManager) CreateProcess() (int, error) {
-	pid := len(pm.pss)
 	ps, err := NewProcess()
 	if err != nil {
 		return -1, err
 	}
-	pm.pss = append(pm.pss, ps)
-	pm.cpid = pid
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	pid, err := pm.insert(ps)
+	if err != nil {
+		return -1, err
+	}
+	pm.attach(pid)
 	return pid, nil
 }
 
-func (pm *ProcessManager) DestroyProcess(pid int) error {
-	if len(pm.pss) <= pid {
-		return fmt.Errorf("not found pid %v", pid)
+// ForkProcess copies the process attached to the calling thread into a new process which no thread is attached to.
+func (pm *ProcessManager) ForkProcess() (int, error) {
+	ps, err := pm.CurrentProcess()
+	if err != nil {
+		return -1, err
 	}
-	pm.pss[pid] = nil
+	forked, err := ps.Fork()
+	if err != nil {
+		return -1, err
+	}
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	return pm.insert(forked)
+}
+
+func (pm *ProcessManager) insert(ps *Process) (int, error) {
+	var index int
+	if n := len(pm.free); n > 0 {
+		index = pm.free[n-1]
+		pm.free = pm.free[:n-1]
+	} else if len(pm.slots) <= pidIndexMask {
+		index = len(pm.slots)
+		pm.slots = append(pm.slots, slot{})
+	} else {
+		return -1, errors.New("too many processes")
+	}
+	pm.slots[index].ps = ps
+	return makePID(index, pm.slots[index].generation), nil
+}
+
+func (pm *ProcessManager) DestroyProcess() error {
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	tid := currentThreadID()
+	pid, ok := pm.threads[tid]
+	if !ok {
+		return errors.New("no process is attached")
+	}
+	if _, err := pm.lookup(pid); err != nil {
+		return err
+	}
+	index, _ := splitPID(pid)
+	pm.slots[index].ps = nil
+	pm.slots[index].generation = (pm.slots[index].generation + 1) & pidGenerationMask
+	pm.free = append(pm.free, index)
+	delete(pm.threads, tid)
 	return nil
 }
 
 func (pm *ProcessManager) CurrentProcess() (*Process, error) {
-	if len(pm.pss) <= pm.cpid {
-		return nil, fmt.Errorf("not found pid %v", pm.cpid)
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	pid, ok := pm.threads[currentThreadID()]
+	if !ok {
+		return nil, errors.New("no process is attached")
 	}
-	ps := pm.pss[pm.cpid]
-	if ps == nil {
-		return nil, errors.New("deleted process")
+	return pm.lookup(pid)
+}
+
+// SetLastError records err as the last error of the calling thread.
+func (pm *ProcessManager) SetLastError(err error) {
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	if pm.errors == nil {
+		pm.errors = make(map[uint64]string)
 	}
-	return ps, nil
+	pm.errors[currentThreadID()] = err.Error()
+}
+
+// LastError returns the last error of the calling thread.
+func (pm *ProcessManager) LastError() (string, bool) {
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	err, ok := pm.errors[currentThreadID()]
+	return err, ok
 }
 
-func (pm *ProcessManager) CurrentProcessID() int {
-	return pm.cpid
+// TakeLastError returns the last error of the calling thread, and clears it.
+func (pm *ProcessManager) TakeLastError() (string, bool) {
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	err, ok := pm.errors[currentThreadID()]
+	delete(pm.errors, currentThreadID())
+	return err, ok
+}
+
+func (pm *ProcessManager) LiveProcessCount() int {
+	pm.mu.Lock()
+	defer pm.mu.Unlock()
+	n := 0
+	for _, s := range pm.slots {
+		if s.ps != nil {
+			n++
+		}
+	}
+	return n
 }
 
 var _ contract.Process = (*Process)(nil)
 
 type Process struct {
 	initialized bool
-	debug bool
+	trace *trace
 	kvs sdk.KVStore
 	db          *db.VersionedDB
 
@@ -89,6 +239,9 @@ type Process struct {
 	stateStack *list.List
 	sets db.RWSets
 	entries []*event.Entry
+
+	// committed mirrors the committed state, keyed by contract address followed by key
+	committed map[string][]byte
 }
 
 func NewProcess() (*Process, error) {
@@ -96,7 +249,7 @@ func NewProcess() (*Process, error) {
 	if err != nil {
 		return nil, err
 	}
-	return &Process{kvs: kvs, db: db.NewVersionedDB(kvs.Prefix(common.Address{}.Bytes())), stateStack: list.New()}, nil
+	return &Process{kvs: kvs, db: db.NewVersionedDB(kvs.Prefix(common.Address{}.Bytes())), stateStack: list.New(), committed: make(map[string][]byte)}, nil
 }
 
 func newKVS() (sdk.KVStore, error) {
@@ -158,10 +311,34 @@ func (p *Process) EmitEvent(e *event.Entry) {
 	p.entries = append(p.entries, e)
 }
 
+// Fork returns a process which shares nothing with p but a copy of the committed state and the contract address.
+func (p *Process) Fork() (*Process, error) {
+	kvs, err := newKVS()
+	if err != nil {
+		return nil, err
+	}
+	it := p.kvs.Iterator(nil, nil)
+	defer it.Close()
+	for ; it.Valid(); it.Next() {
+		kvs.Set(it.Key(), it.Value())
+	}
+	forked := &Process{kvs: kvs, stateStack: list.New(), committed: make(map[string][]byte, len(p.committed))}
+	for k, v := range p.committed {
+		forked.committed[k] = v
+	}
+	if p.trace != nil {
+		forked.trace = newTrace()
+	}
+	forked.InitContractAddress(p.contractAddress)
+	return forked, nil
+}
+
 // TODO this method should be moved into NewProcess?
+// InitContractAddress starts a transaction, so a return value of a previous one must not leak into it.
 func (p *Process) InitContractAddress(addr common.Address) {
 	copy(p.contractAddress[:], addr[:])
 	p.db = db.NewVersionedDB(p.kvs.Prefix(p.contractAddress.Bytes()))
+	p.res = nil
 }
 
 func (p *Process) PushState(contractAddressBytes contract.Reader) {
@@ -174,6 +351,8 @@ func (p *Process) PushState(contractAddressBytes contract.Reader) {
 		args: p.args,
 		res: p.res,
 		db: p.db,
+		// the length of sets is the number of the write sets of nested calls the caller had made
+		sets: p.sets,
 	})
 	// clear
 	p.initialized = false
@@ -203,6 +382,16 @@ func (p *Process) PopState() {
 	p.stateStack.Remove(elem)
 }
 
+// DiscardState drops the pending writes of the current call and of the nested calls it made.
+func (p *Process) DiscardState() {
+	p.db = db.NewVersionedDB(p.kvs.Prefix(p.contractAddress.Bytes()))
+	if elem := p.stateStack.Front(); elem != nil {
+		p.sets = p.sets[:len(elem.Value.(Process).sets)]
+	} else {
+		p.sets = nil
+	}
+}
+
 func (p *Process) CommitState() error {
 	sets := make([]*db.RWSet, len(p.sets))
 	copy(sets[:], p.sets)
@@ -211,34 +400,173 @@ func (p *Process) CommitState() error {
 		Items:   p.db.RWSetItems(),
 	}
 	sets = append(sets, set)
-	if p.debug {
-		printRWSets(sets)
+	for _, s := range sets {
+		if len(s.Items.WriteSet) == 0 {
+			continue
+		}
+		writes := [][]byte{s.Address.Bytes()}
+		for _, w := range s.Items.WriteSet {
+			writes = append(writes, w.Key, w.Value)
+		}
+		p.traceAt(s.Address, "commit_state", writes, 0, nil)
 	}
 	p.sets = nil
-	return db.CommitState(p.kvs, sets, db.Version{1, 1}, db.NewKeyMaps())
+	if err := db.CommitState(p.kvs, sets, db.Version{1, 1}, db.NewKeyMaps()); err != nil {
+		return err
+	}
+	for _, s := range sets {
+		for _, w := range s.Items.WriteSet {
+			p.committed[string(s.Address.Bytes())+string(w.Key)] = w.Value
+		}
+	}
+	return nil
 }
 
-func (p *Process) SetDebug(flag uint8) {
-	if flag == 0 {
-		p.debug = false
-	} else if flag == 1 {
-		p.debug = true
-	} else {
-		panic("unknown flag")
+// SerializeState encodes the committed state as triples of length-prefixed contract address, key and value,
+// ordered by address and key.
+func (p *Process) SerializeState() []byte {
+	keys := make([]string, 0, len(p.committed))
+	for k := range p.committed {
+		keys = append(keys, k)
+	}
+	sort.Strings(keys)
+	var bs []byte
+	for _, k := range keys {
+		for _, b := range [][]byte{[]byte(k[:common.AddressLength]), []byte(k[common.AddressLength:]), p.committed[k]} {
+			var size [4]byte
+			binary.BigEndian.PutUint32(size[:], uint32(len(b)))
+			bs = append(bs, size[:]...)
+			bs = append(bs, b...)
+		}
 	}
+	return bs
 }
 
-func printRWSets(sets []*db.RWSet) {
-	for i, s := range sets {
-		fmt.Printf("#%v Address=%v\n", i, s.Address.Hex())
-		for _, r := range s.Items.ReadSet {
-			fmt.Printf("Read key=%v\n", string(r.Key))
-		}
+// SerializeWriteSet encodes the keys of the pending writes as length-prefixed contract address and key,
+// followed by the value committed for the key, whose length is 0xffffffff if the key isn't committed.
+func (p *Process) SerializeWriteSet() []byte {
+	sets := make([]*db.RWSet, len(p.sets))
+	copy(sets[:], p.sets)
+	sets = append(sets, &db.RWSet{
+		Address: p.contractAddress,
+		Items:   p.db.RWSetItems(),
+	})
+	written := make(map[string]bool)
+	for _, s := range sets {
 		for _, w := range s.Items.WriteSet {
-			fmt.Printf("Write key=%v value=%v\n", string(w.Key), string(w.Value))
+			written[string(s.Address.Bytes())+string(w.Key)] = true
 		}
-		fmt.Println("==============================")
 	}
+	keys := make([]string, 0, len(written))
+	for k := range written {
+		keys = append(keys, k)
+	}
+	sort.Strings(keys)
+	var bs []byte
+	for _, k := range keys {
+		for _, b := range [][]byte{[]byte(k[:common.AddressLength]), []byte(k[common.AddressLength:])} {
+			var size [4]byte
+			binary.BigEndian.PutUint32(size[:], uint32(len(b)))
+			bs = append(bs, size[:]...)
+			bs = append(bs, b...)
+		}
+		var size [4]byte
+		v, ok := p.committed[k]
+		if !ok {
+			binary.BigEndian.PutUint32(size[:], 0xffffffff)
+			bs = append(bs, size[:]...)
+			continue
+		}
+		binary.BigEndian.PutUint32(size[:], uint32(len(v)))
+		bs = append(bs, size[:]...)
+		bs = append(bs, v...)
+	}
+	return bs
+}
+
+func (p *Process) SetTrace(flag uint8) error {
+	switch flag {
+	case 0:
+		p.trace = nil
+	case 1:
+		if p.trace == nil {
+			p.trace = newTrace()
+		}
+	default:
+		return fmt.Errorf("unknown trace flag %v", flag)
+	}
+	return nil
+}
+
+// trace records the host calls of a process while tracing is enabled.
+type trace struct {
+	start  time.Time
+	events []traceEvent
+}
+
+type traceEvent struct {
+	timestamp uint64
+	depth     uint32
+	contract  common.Address
+	name      string
+	args      [][]byte
+	result    int32
+	output    []byte
+}
+
+func newTrace() *trace {
+	return &trace{start: time.Now()}
+}
+
+// Trace records a host call of the current contract if tracing is enabled.
+func (p *Process) Trace(name string, args [][]byte, result int, output []byte) {
+	p.traceAt(p.contractAddress, name, args, result, output)
+}
+
+func (p *Process) traceAt(contract common.Address, name string, args [][]byte, result int, output []byte) {
+	if p.trace == nil {
+		return
+	}
+	p.trace.events = append(p.trace.events, traceEvent{
+		timestamp: uint64(time.Since(p.trace.start).Nanoseconds()),
+		depth:     uint32(p.stateStack.Len()),
+		contract:  contract,
+		name:      name,
+		args:      args,
+		result:    int32(result),
+		output:    append([]byte(nil), output...),
+	})
+}
+
+func (p *Process) ClearTrace() {
+	if p.trace != nil {
+		p.trace.events = nil
+	}
+}
+
+// SerializeTrace encodes each event as length-prefixed name, contract address, timestamp, depth, result, output,
+// the number of arguments and the arguments, where the numbers are big-endian.
+func (p *Process) SerializeTrace() []byte {
+	if p.trace == nil {
+		return nil
+	}
+	var bs []byte
+	for _, e := range p.trace.events {
+		var ts [8]byte
+		binary.BigEndian.PutUint64(ts[:], e.timestamp)
+		var depth, result, nArgs [4]byte
+		binary.BigEndian.PutUint32(depth[:], e.depth)
+		binary.BigEndian.PutUint32(result[:], uint32(e.result))
+		binary.BigEndian.PutUint32(nArgs[:], uint32(len(e.args)))
+		fields := [][]byte{[]byte(e.name), e.contract.Bytes(), ts[:], depth[:], result[:], e.output, nArgs[:]}
+		for _, b := range append(fields, e.args...) {
+			var size [4]byte
+			binary.BigEndian.PutUint32(size[:], uint32(len(b)))
+			bs = append(bs, size[:]...)
+			bs = append(bs, b...)
+		}
+	}
+	return bs
 }
 
 type value struct {
@@ -250,9 +578,11 @@ func (val *value) Write(v []byte) int {
 	if len(v) > val.len {
 		return -1
 	}
-	for i, b := range v {
-		*(*byte)(unsafe.Pointer(val.pos + uintptr(i)*unsafe.Sizeof(byte(0)))) = b
+	if len(v) == 0 {
+		return 0
 	}
+	// copy in one shot instead of byte by byte, as values are read with a buffer of their full length
+	copy((*[1 << 30]byte)(unsafe.Pointer(val.pos))[:len(v):len(v)], v)
 	return len(v)
 }
 
@@ -267,6 +597,14 @@ func (val *value) Len() int {
 	return val.len
 }
 
+// written returns the bytes a host call wrote into the buffer at ptr, for tracing.
+func written(ptr uintptr, ret int) []byte {
+	if ret <= 0 {
+		return nil
+	}
+	return C.GoBytes(unsafe.Pointer(ptr), C.int(ret))
+}
+
 func NewReader(pos uintptr, len int) contract.Reader {
 	return &value{pos: pos, len: len}
 }
@@ -2,7 +2,8 @@ package main
 
 import "C"
 import (
-	"log"
+	"encoding/binary"
+	"errors"
 
 	"github.com/bluele/hypermint/pkg/contract"
 	"github.com/bluele/hypermint/pkg/contract/event"
@@ -12,38 +13,55 @@ import (
 var (
 	processManager = new(ProcessManager)
 	zeroAddress common.Address
+
+	errInitialized = errors.New("the process is already initialized")
 )
 
+// fail records err as the last error of the calling thread, and returns the status of a failed host call.
+func fail(err error) int {
+	processManager.SetLastError(err)
+	return -1
+}
+
 //export __init_process
 func __init_process() int {
 	pid, err := processManager.CreateProcess()
 	if err != nil {
-		log.Println("__init_process:", err)
-		return -1
+		return fail(err)
 	}
 	return pid
 }
 
 //export __destroy_process
 func __destroy_process() int {
-	pid := processManager.CurrentProcessID()
-	err := processManager.DestroyProcess(pid)
-	if err != nil {
-		log.Println(err)
-		return -1
+	if err := processManager.DestroyProcess(); err != nil {
+		return fail(err)
 	}
 	return 0
 }
 
+//export __fork_process
+func __fork_process() int {
+	pid, err := processManager.ForkProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return pid
+}
+
+//export __live_process_count
+func __live_process_count() int {
+	return processManager.LiveProcessCount()
+}
+
 //export __init_contract_address
 func __init_contract_address(ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	if ps.initialized {
-		return -1
+		return fail(errInitialized)
 	}
 	var addr common.Address
 	copy(addr[:], NewReader(ptr, int(len)).Read())
@@ -55,11 +73,10 @@ func __init_contract_address(ptr uintptr, len C.int) int {
 func __init_sender(ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	if ps.initialized {
-		return -1
+		return fail(errInitialized)
 	}
 	copy(ps.sender[:], NewReader(ptr, int(len)).Read())
 	return 0
@@ -69,11 +86,10 @@ func __init_sender(ptr uintptr, len C.int) int {
 func __init_push_arg(ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	if ps.initialized {
-		return -1
+		return fail(errInitialized)
 	}
 	ps.args.PushBytes(NewReader(ptr, int(len)).Read())
 	return 0
@@ -83,16 +99,14 @@ func __init_push_arg(ptr uintptr, len C.int) int {
 func __init_args(ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	if ps.initialized {
-		return -1
+		return fail(errInitialized)
 	}
 	args, err := contract.DeserializeArgs(NewReader(ptr, int(len)).Read())
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	ps.args = args
 	return 0
@@ -102,11 +116,10 @@ func __init_args(ptr uintptr, len C.int) int {
 func __init_done() int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	if ps.initialized {
-		return -1
+		return fail(errInitialized)
 	}
 	ps.initialized = true
 	return 0
@@ -116,8 +129,7 @@ func __init_done() int {
 func __clear() int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	copy(ps.sender[:], zeroAddress[:])
 	ps.args = contract.Args{}
@@ -129,61 +141,179 @@ func __clear() int {
 func __commit_state() int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	if err := ps.CommitState(); err != nil {
-		log.Println("__commit_state:", err)
-		return -1
+		return fail(err)
 	}
 	return 0
 }
 
+//export __discard_state
+func __discard_state() int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	ps.DiscardState()
+	return 0
+}
+
 //export __get_return_value
 func __get_return_value(offset C.int, ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	return contract.WriteBuf(ps, NewWriter(ptr, int(len)), int(offset), ps.res)
 }
 
+//export __get_return_value_len
+func __get_return_value_len() int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return len(ps.res)
+}
+
 //export __get_event
 func __get_event(namePtr uintptr, nameLen C.int, idx C.int, offset C.int, bufPtr uintptr, bufLen C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
+		return fail(err)
+	}
+	e := findEvent(ps, string(NewReader(namePtr, int(nameLen)).Read()), int(idx))
+	if e == nil {
 		return -1
 	}
-	em := make(map[string][]*event.Entry)
-	for _, e := range ps.entries {
-		em[string(e.Name)] = append(em[string(e.Name)], e)
+	return contract.WriteBuf(ps, NewWriter(bufPtr, int(bufLen)), int(offset), e.Value)
+}
+
+//export __get_event_len
+func __get_event_len(namePtr uintptr, nameLen C.int, idx C.int) int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
 	}
-	name := string(NewReader(namePtr, int(nameLen)).Read())
-	es := em[name]
-	if len(es) <= int(idx) {
+	e := findEvent(ps, string(NewReader(namePtr, int(nameLen)).Read()), int(idx))
+	if e == nil {
 		return -1
 	}
-	return contract.WriteBuf(ps, NewWriter(bufPtr, int(bufLen)), int(offset), es[int(idx)].Value)
+	return len(e.Value)
+}
+
+// findEvent returns the idx-th event named name, or nil if there is none.
+func findEvent(ps *Process, name string, idx int) *event.Entry {
+	for _, e := range ps.entries {
+		if string(e.Name) != name {
+			continue
+		}
+		if idx == 0 {
+			return e
+		}
+		idx--
+	}
+	return nil
+}
+
+//export __get_events
+func __get_events(offset C.int, bufPtr uintptr, bufLen C.int) int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return contract.WriteBuf(ps, NewWriter(bufPtr, int(bufLen)), int(offset), serializeEvents(ps.entries))
+}
+
+//export __get_events_len
+func __get_events_len() int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return len(serializeEvents(ps.entries))
+}
+
+// serializeEvents encodes each entry as a length-prefixed name followed by a length-prefixed value.
+func serializeEvents(entries []*event.Entry) []byte {
+	var bs []byte
+	for _, e := range entries {
+		for _, b := range [][]byte{[]byte(e.Name), e.Value} {
+			var size [4]byte
+			binary.BigEndian.PutUint32(size[:], uint32(len(b)))
+			bs = append(bs, size[:]...)
+			bs = append(bs, b...)
+		}
+	}
+	return bs
+}
+
+//export __get_state
+func __get_state(offset C.int, bufPtr uintptr, bufLen C.int) int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return contract.WriteBuf(ps, NewWriter(bufPtr, int(bufLen)), int(offset), ps.SerializeState())
+}
+
+//export __get_state_len
+func __get_state_len() int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return len(ps.SerializeState())
+}
+
+//export __get_write_set
+func __get_write_set(offset C.int, bufPtr uintptr, bufLen C.int) int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return contract.WriteBuf(ps, NewWriter(bufPtr, int(bufLen)), int(offset), ps.SerializeWriteSet())
+}
+
+//export __get_write_set_len
+func __get_write_set_len() int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return len(ps.SerializeWriteSet())
 }
 
 //export __get_sender
 func __get_sender(ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
-	return contract.GetSender(ps, NewWriter(ptr, int(len)))
+	ret := contract.GetSender(ps, NewWriter(ptr, int(len)))
+	ps.Trace("get_sender", nil, ret, written(ptr, ret))
+	return ret
 }
 
 //export __get_contract_address
 func __get_contract_address(ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
+	}
+	ret := contract.GetContractAddress(ps, NewWriter(ptr, int(len)))
+	ps.Trace("get_contract_address", nil, ret, written(ptr, ret))
+	return ret
+}
+
+// __get_init_contract_address reads the contract address like __get_contract_address,
+// but isn't traced since only the harness calls it.
+//export __get_init_contract_address
+func __get_init_contract_address(ptr uintptr, len C.int) int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
 	}
 	return contract.GetContractAddress(ps, NewWriter(ptr, int(len)))
 }
@@ -192,65 +322,76 @@ func __get_contract_address(ptr uintptr, len C.int) int {
 func __get_arg(idx, offset C.int, ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
-	return contract.GetArg(ps, int(idx), int(offset), NewWriter(ptr, int(len)))
+	ret := contract.GetArg(ps, int(idx), int(offset), NewWriter(ptr, int(len)))
+	var idxBytes [4]byte
+	binary.BigEndian.PutUint32(idxBytes[:], uint32(idx))
+	ps.Trace("get_arg", [][]byte{idxBytes[:]}, ret, written(ptr, ret))
+	return ret
 }
 
 //export __set_response
 func __set_response(ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
-	return contract.SetResponse(ps, NewReader(ptr, int(len)))
+	res := NewReader(ptr, int(len))
+	ret := contract.SetResponse(ps, res)
+	ps.Trace("set_response", [][]byte{res.Read()}, ret, nil)
+	return ret
 }
 
 //export __log
 func __log(ptr uintptr, len C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
-	return contract.Log(ps, NewReader(ptr, int(len)))
+	msg := NewReader(ptr, int(len))
+	ret := contract.Log(ps, msg)
+	ps.Trace("log", [][]byte{msg.Read()}, ret, nil)
+	return ret
 }
 
 //export __read_state
 func __read_state(keyPtr uintptr, keyLen, offset C.int, bufPtr uintptr, bufLen C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	key := NewReader(keyPtr, int(keyLen))
 	buf := NewWriter(bufPtr, int(bufLen))
-	return contract.ReadState(ps, key, int(offset), buf)
+	ret := contract.ReadState(ps, key, int(offset), buf)
+	ps.Trace("read_state", [][]byte{key.Read()}, ret, written(bufPtr, ret))
+	return ret
 }
 
 //export __write_state
 func __write_state(keyPtr uintptr, keyLen C.int, valPtr uintptr, valLen C.int) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	key := NewReader(keyPtr, int(keyLen))
 	val := NewReader(valPtr, int(valLen))
-	return contract.WriteState(ps, key, val)
+	ret := contract.WriteState(ps, key, val)
+	ps.Trace("write_state", [][]byte{key.Read(), val.Read()}, ret, nil)
+	return ret
 }
 
-//export __get_mutex
-func __get_mutex(pid int) int {
-	processManager.GetMutex(pid)
+//export __attach_process
+func __attach_process(pid C.int) int {
+	if err := processManager.Attach(int(pid)); err != nil {
+		return fail(err)
+	}
 	return 0
 }
 
-//export __release_mutex
-func __release_mutex() int {
-	processManager.ReleaseMutex()
+//export __detach_process
+func __detach_process() int {
+	processManager.Detach()
 	return 0
 }
 
@@ -264,17 +405,17 @@ func __ecrecover(
 ) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
-	return contract.ECRecover(
-		ps,
+	args := []contract.Reader{
 		NewReader(h, int(hLen)),
 		NewReader(v, int(vLen)),
 		NewReader(r, int(rLen)),
 		NewReader(s, int(sLen)),
-		NewWriter(buf, int(bufLen)),
-	)
+	}
+	ret := contract.ECRecover(ps, args[0], args[1], args[2], args[3], NewWriter(buf, int(bufLen)))
+	ps.Trace("ecrecover", readAll(args), ret, written(buf, ret))
+	return ret
 }
 
 //export __ecrecover_address
@@ -287,17 +428,17 @@ func __ecrecover_address(
 ) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
-	return contract.ECRecoverAddress(
-		ps,
+	args := []contract.Reader{
 		NewReader(h, int(hLen)),
 		NewReader(v, int(vLen)),
 		NewReader(r, int(rLen)),
 		NewReader(s, int(sLen)),
-		NewWriter(buf, int(bufLen)),
-	)
+	}
+	ret := contract.ECRecoverAddress(ps, args[0], args[1], args[2], args[3], NewWriter(buf, int(bufLen)))
+	ps.Trace("ecrecover_address", readAll(args), ret, written(buf, ret))
+	return ret
 }
 
 //export __emit_event
@@ -309,14 +450,12 @@ func __emit_event(
 ) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
-	return contract.EmitEvent(
-		ps,
-		NewReader(ev, int(evLen)),
-		NewReader(data, int(dataLen)),
-	)
+	name, value := NewReader(ev, int(evLen)), NewReader(data, int(dataLen))
+	ret := contract.EmitEvent(ps, name, value)
+	ps.Trace("emit_event", [][]byte{name.Read(), value.Read()}, ret, nil)
+	return ret
 }
 
 //export __push_contract_state
@@ -326,8 +465,7 @@ func __push_contract_state(
 ) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	ps.PushState(NewReader(addrPtr, int(addrLen)))
 	return 0
@@ -337,23 +475,110 @@ func __push_contract_state(
 func __pop_contract_state() int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
 	}
 	ps.PopState()
 	return 0
 }
 
-//export __set_debug
-func __set_debug(flag C.uint) int {
+//export __set_trace
+func __set_trace(flag C.uint) int {
 	ps, err := processManager.CurrentProcess()
 	if err != nil {
-		log.Println(err)
-		return -1
+		return fail(err)
+	}
+	if err := ps.SetTrace(uint8(flag)); err != nil {
+		return fail(err)
+	}
+	return 0
+}
+
+// __trace_event records an event which only the harness knows about, e.g. the entry and status of a contract call.
+//export __trace_event
+func __trace_event(namePtr uintptr, nameLen C.int, argsPtr uintptr, argsLen C.int, result C.int, outputPtr uintptr, outputLen C.int) int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
 	}
-	ps.SetDebug(uint8(flag))
+	args, err := deserializeValues(NewReader(argsPtr, int(argsLen)).Read())
+	if err != nil {
+		return fail(err)
+	}
+	name := string(NewReader(namePtr, int(nameLen)).Read())
+	ps.Trace(name, args, int(result), NewReader(outputPtr, int(outputLen)).Read())
 	return 0
 }
 
+//export __get_trace
+func __get_trace(offset C.int, bufPtr uintptr, bufLen C.int) int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return contract.WriteBuf(ps, NewWriter(bufPtr, int(bufLen)), int(offset), ps.SerializeTrace())
+}
+
+//export __get_trace_len
+func __get_trace_len() int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	return len(ps.SerializeTrace())
+}
+
+//export __clear_trace
+func __clear_trace() int {
+	ps, err := processManager.CurrentProcess()
+	if err != nil {
+		return fail(err)
+	}
+	ps.ClearTrace()
+	return 0
+}
+
+// __get_last_error copies the last error of the calling thread into the buffer, and clears it.
+//export __get_last_error
+func __get_last_error(ptr uintptr, len C.int) int {
+	err, ok := processManager.TakeLastError()
+	if !ok {
+		return -1
+	}
+	return NewWriter(ptr, int(len)).Write([]byte(err))
+}
+
+//export __get_last_error_len
+func __get_last_error_len() int {
+	err, ok := processManager.LastError()
+	if !ok {
+		return -1
+	}
+	return len(err)
+}
+
+func readAll(rs []contract.Reader) [][]byte {
+	bs := make([][]byte, len(rs))
+	for i, r := range rs {
+		bs[i] = r.Read()
+	}
+	return bs
+}
+
+// deserializeValues decodes values serialized as a sequence of 4-byte big-endian length prefixed values.
+func deserializeValues(bs []byte) ([][]byte, error) {
+	var values [][]byte
+	for len(bs) > 0 {
+		if len(bs) < 4 {
+			return nil, errors.New("unexpected eof")
+		}
+		size := int(binary.BigEndian.Uint32(bs[:4]))
+		if len(bs) < 4+size {
+			return nil, errors.New("unexpected eof")
+		}
+		values = append(values, bs[4:4+size])
+		bs = bs[4+size:]
+	}
+	return values, nil
+}
 
 func main() {}